@@ -0,0 +1,140 @@
+//! Slice-based convolution against the per-pixel loops it replaced.
+//!
+//! `cargo bench --bench convolution [-- WIDTH HEIGHT]`, 4000x3000 (12 MP) by default. The
+//! thread comparison only shows a speedup on machines with more than one core.
+
+use std::{
+    env,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use image::{DynamicImage, GenericImage, GenericImageView, GrayImage, Luma, Rgba};
+use rust_server_playground::{
+    canny, convolve, gaussian_blur, parallel, sobel_operator, BorderMode, CannyParams, Filter, Kernel, Operators,
+    ResponseImage,
+};
+
+const RUNS: usize = 5;
+
+/// Median wall time of `RUNS` calls.
+fn time<R>(mut f: impl FnMut() -> R) -> Duration {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+fn report(name: &str, before: Duration, after: Duration) {
+    println!(
+        "{name:<28} {:>9.1} ms {:>9.1} ms {:>7.2}x",
+        before.as_secs_f64() * 1e3,
+        after.as_secs_f64() * 1e3,
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+}
+
+/// The Sobel loop as it was: every tap goes through `DynamicImage::get_pixel`, which converts
+/// the pixel to RGBA, and the results are written back as RGBA into 8-bit `DynamicImage`s.
+/// Like the original it leaves the border pixels black.
+fn per_pixel_sobel(image: &DynamicImage, operators: &Operators) -> (DynamicImage, DynamicImage) {
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let (op_x, op_y) = (operators.x(), operators.y());
+    let size = operators.size() as i32;
+    let before = (size - 1) / 2;
+    let after = size - 1 - before;
+    let scale = 4.0 / operators.gain() as f32;
+
+    let mut magnitude = DynamicImage::new_luma8(image.width(), image.height());
+    let mut direction = DynamicImage::new_luma8(image.width(), image.height());
+    for x in before..width - after {
+        for y in before..height - after {
+            let (mut gx, mut gy) = (0.0, 0.0);
+            for i in -before..=after {
+                for j in -before..=after {
+                    let pixel = image.get_pixel((x + i) as u32, (y + j) as u32).0[0] as f32;
+                    gx += pixel * op_x[(j + before) as usize][(i + before) as usize] as f32;
+                    gy += pixel * op_y[(j + before) as usize][(i + before) as usize] as f32;
+                }
+            }
+            let (gx, gy) = (gx * scale, gy * scale);
+            let g = (gx * gx + gy * gy).sqrt() as u8;
+            let angle = gy.atan2(gx).to_degrees().rem_euclid(180.0) as u8;
+            magnitude.put_pixel(x as u32, y as u32, Rgba([g, g, g, 255]));
+            direction.put_pixel(x as u32, y as u32, Rgba([angle, angle, angle, 255]));
+        }
+    }
+    (magnitude, direction)
+}
+
+/// The gradient products of the structure tensor as they were, before the window blur.
+fn per_pixel_products(image: &GrayImage, border: BorderMode) -> [ResponseImage; 3] {
+    let mut products = [0; 3].map(|_| ResponseImage::new(image.width(), image.height()));
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let (x, y) = (x as i64, y as i64);
+            let ix = (border.get(image, x + 1, y).unwrap_or(0.0) - border.get(image, x - 1, y).unwrap_or(0.0)) / 2.0;
+            let iy = (border.get(image, x, y + 1).unwrap_or(0.0) - border.get(image, x, y - 1).unwrap_or(0.0)) / 2.0;
+            for (product, value) in products.iter_mut().zip([ix * ix, ix * iy, iy * iy]) {
+                product.put_pixel(x as u32, y as u32, Luma([value]));
+            }
+        }
+    }
+    products
+}
+
+fn main() {
+    let args: Vec<usize> = env::args().skip(1).filter_map(|arg| arg.parse().ok()).collect();
+    let (width, height) = match args[..] {
+        [width, height, ..] => (width as u32, height as u32),
+        _ => (4000, 3000),
+    };
+
+    let mut state = 0x2545_f491_u32;
+    let image = GrayImage::from_fn(width, height, |x, y| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        let blob = if (x / 200 + y / 150) % 2 == 0 { 170 } else { 50 };
+        Luma([(blob + (state >> 24) % 40) as u8])
+    });
+    let border = BorderMode::Replicate;
+    let threads = parallel::threads();
+
+    println!("{width}x{height}, median of {RUNS} runs, {threads} threads available");
+    println!("{:<28} {:>12} {:>12} {:>8}", "", "per-pixel", "slices", "speedup");
+
+    parallel::set_threads(1);
+    let dynamic = DynamicImage::ImageLuma8(image.clone());
+    for kernel in [Kernel::Sobel3, Kernel::Sobel7] {
+        let operators = kernel.operators();
+        let before = time(|| per_pixel_sobel(&dynamic, &operators));
+        let after = time(|| sobel_operator(&image, &operators, border));
+        report(&format!("sobel {}x{0}, 1 thread", operators.size()), before, after);
+    }
+
+    let before = time(|| per_pixel_products(&image, border));
+    let after = time(|| {
+        let raw = image.as_raw();
+        let (w, h) = (width as usize, height as usize);
+        let ix = convolve(raw, w, h, &Filter::new(&[vec![0.0; 3], vec![-0.5, 0.0, 0.5], vec![0.0; 3]]), border);
+        let iy = convolve(raw, w, h, &Filter::new(&[vec![0.0, -0.5, 0.0], vec![0.0; 3], vec![0.0, 0.5, 0.0]]), border);
+        let product = |a: &[f32], b: &[f32]| -> Vec<f32> { a.iter().zip(b).map(|(a, b)| a * b).collect() };
+        [product(&ix, &ix), product(&ix, &iy), product(&iy, &iy)]
+    });
+    report("gradient products, 1 thread", before, after);
+
+    println!();
+    println!("{:<28} {:>12} {:>12} {:>8}", "", "1 thread", format!("{threads} threads"), "speedup");
+    let serial = time(|| gaussian_blur(&image, 2.0, border));
+    parallel::set_threads(0);
+    report("gaussian sigma 2", serial, time(|| gaussian_blur(&image, 2.0, border)));
+
+    parallel::set_threads(1);
+    let serial = time(|| canny(&image, &CannyParams::default()));
+    parallel::set_threads(0);
+    report("canny", serial, time(|| canny(&image, &CannyParams::default())));
+}
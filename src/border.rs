@@ -0,0 +1,168 @@
+use image::{ImageBuffer, Luma, Primitive};
+
+/// How pixels outside the image are sampled by filters that reach past the border.
+///
+/// Every detector takes one and uses it for all of its steps (blur, gradients,
+/// suppression, structure tensor), so outputs always have the size of the input,
+/// down to 1x1 images.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BorderMode {
+    /// Repeat the nearest border pixel: `aaa|abcd|ddd`.
+    #[default]
+    Replicate,
+    /// Mirror the image including the border pixel: `cba|abcd|dcb`.
+    Reflect,
+    /// Mirror the image around the border pixel: `dcb|abcd|cba`.
+    Reflect101,
+    /// Continue from the opposite side: `bcd|abcd|abc`.
+    Wrap,
+    /// Treat everything outside as this value.
+    Constant(f32),
+    /// Don't compute pixels whose neighbourhood leaves the image; they stay 0.
+    Skip,
+}
+
+impl BorderMode {
+    /// Mode by name; `constant` is black and `clamp` is an alias of `replicate`.
+    pub fn parse(name: &str) -> Option<BorderMode> {
+        match name {
+            "replicate" | "clamp" => Some(BorderMode::Replicate),
+            "reflect" => Some(BorderMode::Reflect),
+            "reflect101" => Some(BorderMode::Reflect101),
+            "wrap" => Some(BorderMode::Wrap),
+            "constant" => Some(BorderMode::Constant(0.0)),
+            "skip" => Some(BorderMode::Skip),
+            _ => None,
+        }
+    }
+
+    /// Index inside `0..len` that stands in for `index`, or `None` for constant and skipped borders.
+    pub fn index(self, index: i64, len: usize) -> Option<usize> {
+        let len = len as i64;
+        if (0..len).contains(&index) {
+            return Some(index as usize);
+        }
+
+        let index = match self {
+            BorderMode::Replicate => index.clamp(0, len - 1),
+            BorderMode::Reflect => {
+                // One period is the image followed by its mirror image.
+                let folded = index.rem_euclid(2 * len);
+                if folded < len { folded } else { 2 * len - 1 - folded }
+            }
+            BorderMode::Reflect101 if len == 1 => 0,
+            BorderMode::Reflect101 => {
+                let period = 2 * (len - 1);
+                let folded = index.rem_euclid(period);
+                if folded < len { folded } else { period - folded }
+            }
+            BorderMode::Wrap => index.rem_euclid(len),
+            BorderMode::Constant(_) | BorderMode::Skip => return None,
+        };
+        Some(index as usize)
+    }
+
+    /// Value at `index` of a row or column of samples, or `None` when it is skipped.
+    pub fn sample(self, samples: &[f32], index: i64) -> Option<f32> {
+        match (self.index(index, samples.len()), self) {
+            (Some(index), _) => Some(samples[index]),
+            (None, BorderMode::Constant(value)) => Some(value),
+            (None, _) => None,
+        }
+    }
+
+    /// Pixel at a position that may lie outside the image, or `None` when it is skipped.
+    pub fn get<T: Primitive + Into<f32>>(self, image: &ImageBuffer<Luma<T>, Vec<T>>, x: i64, y: i64) -> Option<f32> {
+        match (self.index(x, image.width() as usize), self.index(y, image.height() as usize), self) {
+            (Some(x), Some(y), _) => Some(image.get_pixel(x as u32, y as u32).0[0].into()),
+            (_, _, BorderMode::Constant(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+    use crate::{
+        canny, harris, harris_corners, shi, sobel, CannyParams, HarrisParams, Kernel, ShiParams, SobelParams,
+    };
+
+    const MODES: [BorderMode; 6] = [
+        BorderMode::Replicate,
+        BorderMode::Reflect,
+        BorderMode::Reflect101,
+        BorderMode::Wrap,
+        BorderMode::Constant(0.0),
+        BorderMode::Skip,
+    ];
+
+    fn indices(mode: BorderMode, len: usize) -> Vec<Option<usize>> {
+        (-4..len as i64 + 4).map(|i| mode.index(i, len)).collect()
+    }
+
+    #[test]
+    fn maps_outside_indices() {
+        let some = |v: &[usize]| v.iter().map(|&i| Some(i)).collect::<Vec<_>>();
+        assert_eq!(indices(BorderMode::Replicate, 3), some(&[0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 2]));
+        assert_eq!(indices(BorderMode::Reflect, 3), some(&[2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0]));
+        assert_eq!(indices(BorderMode::Reflect101, 3), some(&[0, 1, 2, 1, 0, 1, 2, 1, 0, 1, 2]));
+        assert_eq!(indices(BorderMode::Wrap, 3), some(&[2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0]));
+        assert_eq!(indices(BorderMode::Constant(0.0), 3)[4..7], some(&[0, 1, 2]));
+        assert!(indices(BorderMode::Constant(0.0), 3)[..4].iter().all(Option::is_none));
+        assert_eq!(indices(BorderMode::Skip, 3)[4..7], some(&[0, 1, 2]));
+        assert!(indices(BorderMode::Skip, 3)[7..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn single_pixel() {
+        for mode in [BorderMode::Replicate, BorderMode::Reflect, BorderMode::Reflect101, BorderMode::Wrap] {
+            assert!(indices(mode, 1).iter().all(|i| *i == Some(0)), "{mode:?}");
+        }
+        assert_eq!(BorderMode::Constant(7.0).sample(&[1.0], -1), Some(7.0));
+        assert_eq!(BorderMode::Skip.sample(&[1.0], -1), None);
+    }
+
+    #[test]
+    fn detectors_keep_the_size_of_tiny_images() {
+        for (width, height) in [(1, 1), (1, 6), (6, 1), (2, 2), (3, 3)] {
+            let image = GrayImage::from_fn(width, height, |x, y| Luma([((x * 7 + y * 13) % 5 * 50) as u8]));
+
+            for border in MODES {
+                let context = format!("{width}x{height} {border:?}");
+                let kernel = Kernel::Sobel7;
+
+                let edges = canny(&image, &CannyParams { border, kernel: kernel.clone(), ..CannyParams::default() });
+                assert_eq!(edges.unwrap().dimensions(), (width, height), "canny {context}");
+                let gradients = sobel(&image, &SobelParams { border, kernel, ..SobelParams::default() });
+                assert_eq!(gradients.unwrap().dimensions(), (width, height), "sobel {context}");
+
+                let harris_params = HarrisParams { border, subpixel: true, ..HarrisParams::default() };
+                assert_eq!(harris(&image, &harris_params).unwrap().dimensions(), (width, height), "harris {context}");
+                harris_corners(&image, &harris_params).unwrap();
+                let shi_params = ShiParams { border, subpixel: true, ..ShiParams::default() };
+                assert_eq!(shi(&image, &shi_params).unwrap().dimensions(), (width, height), "shi {context}");
+            }
+        }
+    }
+
+    #[test]
+    fn border_pixels_are_computed_unless_skipped() {
+        // Vertical step edge running through the top and bottom rows.
+        let image = GrayImage::from_fn(12, 12, |x, _| Luma([if x < 6 { 0 } else { 200 }]));
+        let edge_at_top = |border| {
+            let params = SobelParams { border, ..SobelParams::default() };
+            sobel(&image, &params).unwrap().get_pixel(6, 0).0[0]
+        };
+
+        for border in [BorderMode::Replicate, BorderMode::Reflect, BorderMode::Reflect101, BorderMode::Wrap] {
+            assert!(edge_at_top(border) > 0, "{border:?}");
+        }
+        assert_eq!(edge_at_top(BorderMode::Skip), 0);
+
+        let edges = canny(&image, &CannyParams { border: BorderMode::Replicate, ..CannyParams::default() }).unwrap();
+        assert!((0..12).all(|y| edges.get_pixel(5, y).0[0] == 255 || edges.get_pixel(6, y).0[0] == 255));
+    }
+}
@@ -1,150 +1,343 @@
-use std::{f32::consts::PI, io::Cursor};
+use image::{GrayImage, Luma};
+
+use crate::{
+    error::{Error, Result},
+    border::BorderMode,
+    gausian_blur::gaussian_blur,
+    kernel::Kernel,
+    parallel,
+    sobel::{quantize_orientation, sobel_operator},
+    ResponseImage,
+};
+
+/// How non-maximum suppression picks the neighbours a pixel is compared with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Suppression {
+    /// Compare with the two pixels along the gradient snapped to 0, 45, 90 or 135 degrees.
+    #[default]
+    Quantized,
+    /// Compare with the magnitude bilinearly interpolated one pixel along the exact gradient.
+    Interpolated,
+}
 
-// use crate::gausian_blur::apply_gausian_filter;
+impl Suppression {
+    pub fn parse(name: &str) -> Option<Suppression> {
+        match name {
+            "quantized" => Some(Suppression::Quantized),
+            "interpolated" => Some(Suppression::Interpolated),
+            _ => None,
+        }
+    }
+}
 
-use image::{imageops::grayscale, io::Reader as ImageReader, DynamicImage, GenericImage, GenericImageView, Rgba};
+#[derive(Clone, Debug, PartialEq)]
+pub struct CannyParams {
+    /// Standard deviation of the Gaussian blur applied before the gradients.
+    pub sigma: f32,
+    /// Weak edge threshold as a fraction of the full intensity range (0..1).
+    /// Weak pixels only survive when connected to a strong one.
+    pub low_threshold: f32,
+    /// Strong edge threshold as a fraction of the full intensity range (0..1).
+    pub high_threshold: f32,
+    pub suppression: Suppression,
+    /// Gradient operators; the thresholds are relative to the 3x3 Sobel gain for every kernel.
+    pub kernel: Kernel,
+    /// How the blur, the gradients and the suppression sample outside the image.
+    pub border: BorderMode,
+}
 
+impl Default for CannyParams {
+    fn default() -> Self {
+        CannyParams {
+            sigma: 1.0,
+            low_threshold: 0.1,
+            high_threshold: 0.3,
+            suppression: Suppression::Quantized,
+            kernel: Kernel::Sobel3,
+            border: BorderMode::Replicate,
+        }
+    }
+}
 
-pub fn canny(body: &Vec<u8>, gausian_strength: &f32, threshold: &f32) -> Vec<u8> {
+/// Canny edge map of a grayscale image.
+pub fn canny(image: &GrayImage, params: &CannyParams) -> Result<GrayImage> {
+    let blurred = gaussian_blur(image, params.sigma, params.border)?;
+    canny_blurred(&blurred, params)
+}
 
-    let img = ImageReader::new(Cursor::new(body)).with_guessed_format().unwrap().decode().unwrap();
+/// Canny edge map of an image that was already blurred; `params.sigma` is ignored.
+pub fn canny_blurred(blurred: &GrayImage, params: &CannyParams) -> Result<GrayImage> {
+    // Checked before the gradients are computed for nothing.
+    check_thresholds(params)?;
+    let (gradient_mag, gradient_dir) = sobel_operator(blurred, &params.kernel.operators(), params.border);
+    canny_from_gradients(&gradient_mag, &gradient_dir, params)
+}
 
-    let gray_scale_img = grayscale(&img);
-    //let gausian_image = apply_gausian_filter(gray_scale_img.into(), gausian_strength).unwrap(); // My implementation
-    let gausian_image = image::imageops::blur(&gray_scale_img, *gausian_strength);
+/// Canny edge map from the gradients of a blurred image, as [`sobel_operator`] computes them,
+/// for callers that need the gradients too; `params.sigma` and `params.kernel` are ignored.
+pub fn canny_from_gradients(magnitude: &ResponseImage, orientation: &ResponseImage, params: &CannyParams) -> Result<GrayImage> {
+    check_thresholds(params)?;
+    let (low, high) = (params.low_threshold, params.high_threshold);
+    let suppressed_image = non_maximum_suppression(magnitude, orientation, params.suppression, params.border);
 
-    let (gradient_mag, gradient_dir) = sobel_operator(&gausian_image.into());
-    let suppressed_image = non_maximum_suppression(gradient_mag, &gradient_dir);
-    let mut threshold_image = double_threshold(suppressed_image, *threshold);
-    threshold_image = threshold_image.brighten(10);
+    Ok(hysteresis(&suppressed_image, low, high))
+}
 
-    let mut image_bytes = Vec::new();
-    threshold_image.write_to(&mut Cursor::new(&mut image_bytes), image::ImageFormat::Png).unwrap(); 
-    
-    return image_bytes;
+fn check_thresholds(params: &CannyParams) -> Result<()> {
+    let (low, high) = (params.low_threshold, params.high_threshold);
+    if !low.is_finite() || !high.is_finite() || low < 0.0 || low > high {
+        return Err(Error::InvalidParameter(format!(
+            "thresholds must satisfy 0 <= low <= high, got low {low} and high {high}"
+        )));
+    }
+    Ok(())
 }
 
-fn sobel_operator(image: &DynamicImage) -> (DynamicImage, DynamicImage) {
-    let width = image.width() as i32;
-    let height = image.height() as i32;
-
-    // Sobel operator coefficients
-    let sobel_x = vec![
-        vec![-1, 0, 1],
-        vec![-2, 0, 2],
-        vec![-1, 0, 1],
-    ];
-
-    let sobel_y = vec![
-        vec![-1, -2, -1],
-        vec![0, 0, 0],
-        vec![1, 2, 1],
-    ];
-
-    let mut gradient_magnitude = DynamicImage::new_luma8(image.width(), image.height());
-    let mut gradient_direction = DynamicImage::new_luma8(image.width(), image.height());
-
-    let mut max_gradient = 0;
-
-    // Compute gradient magnitudes
-    for x in 1..width-1 {
-        for y in 1..height-1 {
-            let mut gx = 0.0;
-            let mut gy = 0.0;
-
-            for i in -1..=1 {
-                for j in -1..=1 {
-                    let pixel = image.get_pixel((x + i) as u32, (y + j) as u32).0[0] as i32;
-                    gx += (pixel * sobel_x[(i + 1) as usize][(j + 1) as usize]) as f32;
-                    gy += (pixel * sobel_y[(i + 1) as usize][(j + 1) as usize]) as f32;
-                }
+fn non_maximum_suppression(
+    grad_mag: &ResponseImage,
+    grad_dir: &ResponseImage,
+    mode: Suppression,
+    border: BorderMode,
+) -> ResponseImage {
+
+    let width = grad_mag.width();
+    let height = grad_mag.height();
+
+    parallel::luma_from_fn(width, height, |x, y| {
+        let mag = grad_mag.get_pixel(x, y).0[0];
+        let direction = grad_dir.get_pixel(x, y).0[0];
+        let (x, y) = (x as i64, y as i64);
+
+        let neighbours = match mode {
+            Suppression::Quantized => {
+                // The y axis points down: 45 degrees runs to the bottom right.
+                let (neigh1, neigh2) = match quantize_orientation(direction) {
+                    0 => ((x + 1, y), (x - 1, y)),           // Horizontal
+                    45 => ((x + 1, y + 1), (x - 1, y - 1)),  // 45-degree diagonal
+                    90 => ((x, y + 1), (x, y - 1)),          // Vertical
+                    _ => ((x - 1, y + 1), (x + 1, y - 1)),   // 135-degree diagonal
+                };
+                let value = |(nx, ny): (i64, i64)| border.get(grad_mag, nx, ny);
+                value(neigh1).zip(value(neigh2))
+            }
+            Suppression::Interpolated => {
+                let (dy, dx) = direction.to_radians().sin_cos();
+                let (x, y) = (x as f32, y as f32);
+                bilinear(grad_mag, x + dx, y + dy, border).zip(bilinear(grad_mag, x - dx, y - dy, border))
             }
+        };
 
-            let g = f32::sqrt(gx * gx + gy * gy) as i32;
-            max_gradient = max_gradient.max(g);
-            gradient_magnitude.put_pixel(x as u32, y as u32, Rgba([g as u8, g as u8, g as u8, 255]));
-
-            // Compute gradient direction
-            let angle = gy.atan2(gx);
-            let angle_deg = (angle * (180.0 / PI)).round() as i32;
-            
-            let normalized_angle = match angle_deg {
-                0..=22 => 0,   // Horizontal
-                23..=68 => 45,   // 45-degree diagonal
-                69..=112 => 90, // vetical-degree diagonal
-                113..=158 => 135, // 135-degree diagonal
-                _ => 0,         // Vertical
-            };
-
-            gradient_direction.put_pixel(x as u32, y as u32, Rgba([normalized_angle as u8, normalized_angle as u8, normalized_angle as u8, 255]));
-            gradient_magnitude.put_pixel(x as u32, y as u32, Rgba([g as u8, g as u8, g as u8, 255]));
+        // Pixels with a skipped neighbour are suppressed.
+        match neighbours {
+            Some((neigh1_val, neigh2_val)) if mag >= neigh1_val && mag >= neigh2_val => mag,
+            _ => 0.0,
         }
-    }
+    })
+}
 
-    // // Normalize gradient magnitudes
-    // for x in 1..width-1 {
-    //     for y in 1..height-1 {
-    //         let pixel = gradient_magnitude.get_pixel(x as u32, y as u32).0[0] as f32;
-    //         let normalized_pixel = (pixel / max_gradient as f32 * 255.0).min(255.0) as u8;
-    //         gradient_magnitude.put_pixel(x as u32, y as u32, Rgba([normalized_pixel, normalized_pixel, normalized_pixel, 255]));
-    //     }
-    // }
+/// Magnitude at a sub-pixel position, interpolated from the four surrounding pixels.
+fn bilinear(image: &ResponseImage, x: f32, y: f32, border: BorderMode) -> Option<f32> {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
 
-    (gradient_magnitude, gradient_direction)
+    let value = |x: i64, y: i64| border.get(image, x, y);
+    let top = value(x0, y0)? * (1.0 - fx) + value(x0 + 1, y0)? * fx;
+    let bottom = value(x0, y0 + 1)? * (1.0 - fx) + value(x0 + 1, y0 + 1)? * fx;
+    Some(top * (1.0 - fy) + bottom * fy)
 }
 
-fn non_maximum_suppression(grad_mag: DynamicImage, grad_dir: &DynamicImage) -> DynamicImage {
-
-    let width = grad_mag.width();
-    let height = grad_mag.height();
+const NOT_EDGE: u8 = 0;
+const WEAK_EDGE: u8 = 1;
+const STRONG_EDGE: u8 = 2;
+
+/// Hysteresis thresholding: pixels at or above `high` are strong edges, pixels
+/// at or above `low` are kept only if they are 8-connected to a strong edge.
+/// Both are fractions of 255, compared against the unscaled gradient magnitude.
+fn hysteresis(image: &ResponseImage, low: f32, high: f32) -> GrayImage {
+    let width = image.width();
+    let height = image.height();
+
+    let low = low * 255.0;
+    let high = high * 255.0;
+    // Suppressed pixels are 0 and never count as weak edges, even with `low == 0`.
+    let class = parallel::luma_from_fn(width, height, |x, y| match image.get_pixel(x, y).0[0] {
+        v if v == 0.0 || v < low => NOT_EDGE,
+        v if v < high => WEAK_EDGE,
+        _ => STRONG_EDGE,
+    });
+    let class = |x: u32, y: u32| class.get_pixel(x, y).0[0];
+
+    let mut edges = GrayImage::new(width, height);
+    let mut stack = Vec::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            if class(x, y) != STRONG_EDGE || edges.get_pixel(x, y).0[0] != 0 {
+                continue;
+            }
 
-    let mut suppresed_image = DynamicImage::new_luma8(width, height);
-
-    //FIXME: Get the edges of the image too.
-    for x in 1..width-1 {
-        for y in 1..height-1 {
-            let mag = grad_mag.get_pixel(x, y).0[0];
-            let direction = grad_dir.get_pixel(x, y).0[0];
-
-            let (neigh1, neigh2) = match direction {
-                0 => ((x + 1, y), (x - 1, y)),           // Horizontal
-                45 => ((x + 1, y - 1), (x - 1, y + 1)),  // 45-degree diagonal
-                90 => ((x, y + 1), (x, y - 1)),          // Vertical
-                135 => ((x - 1, y - 1), (x + 1, y + 1)), // 135-degree diagonal
-                _ => continue,
-            };
-            
-            let neigh1_val = grad_mag.get_pixel(neigh1.0, neigh1.1).0[0];
-            let neigh2_val = grad_mag.get_pixel(neigh2.0, neigh2.1).0[0];
-
-            let pixel_value = if mag >= neigh1_val && mag >= neigh2_val {
-                mag
-            } else {
-                0
-            };
-
-            suppresed_image.put_pixel(x, y, Rgba([pixel_value, pixel_value, pixel_value, 255]));
-            
+            // Flood fill the weak pixels reachable from this strong one.
+            edges.put_pixel(x, y, Luma([255]));
+            stack.push((x, y));
+
+            while let Some((cx, cy)) = stack.pop() {
+                for ny in cy.saturating_sub(1)..=(cy + 1).min(height - 1) {
+                    for nx in cx.saturating_sub(1)..=(cx + 1).min(width - 1) {
+                        if edges.get_pixel(nx, ny).0[0] == 0 && class(nx, ny) != NOT_EDGE {
+                            edges.put_pixel(nx, ny, Luma([255]));
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
         }
     }
 
-    return suppresed_image;
+    edges
 }
 
-fn double_threshold(image: DynamicImage, threshold: f32) -> DynamicImage {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sobel::orientation;
 
-    let mut double_threshold_image = DynamicImage::new_luma8(image.width(), image.height());
+    const SIZE: u32 = 32;
+
+    /// Step edge along a line: pixels on the positive side of `a*x + b*y = c` are bright.
+    fn step_edge(a: f32, b: f32, c: f32) -> GrayImage {
+        GrayImage::from_fn(SIZE, SIZE, |x, y| {
+            Luma([if a * x as f32 + b * y as f32 > c { 255 } else { 0 }])
+        })
+    }
 
-    for x in 0..image.width() {
-        for y in 0..image.height() {
-            let value = image.get_pixel(x, y).0[0];
-            if (value as f32) < threshold * 255.0 {
-                double_threshold_image.put_pixel(x, y, Rgba([0,0,0,255]));
-            } else {
-                double_threshold_image.put_pixel(x, y, Rgba([value,value,value,255]));
+    fn edge_pixels(edges: &GrayImage, y: u32) -> Vec<u32> {
+        (0..SIZE).filter(|&x| edges.get_pixel(x, y).0[0] == 255).collect()
+    }
+
+    /// Every row away from the border must hold a thin edge right next to the true edge at `expected(y)`.
+    fn assert_thin_edge(edges: &GrayImage, expected: impl Fn(u32) -> f32) {
+        for y in 4..SIZE - 4 {
+            let pixels = edge_pixels(edges, y);
+            assert!(!pixels.is_empty(), "row {y} lost its edge");
+            assert!(pixels.len() <= 2, "row {y} has a thick edge: {pixels:?}");
+            for x in pixels {
+                assert!((x as f32 - expected(y)).abs() <= 2.0, "row {y}: edge pixel at {x}, expected near {}", expected(y));
             }
         }
     }
 
-    return double_threshold_image;
+    fn params(suppression: Suppression) -> CannyParams {
+        CannyParams { suppression, ..CannyParams::default() }
+    }
+
+    #[test]
+    fn orientation_is_folded_into_half_turn() {
+        assert_eq!(quantize_orientation(orientation(1.0, 0.0)), 0);
+        assert_eq!(quantize_orientation(orientation(-1.0, 0.0)), 0);
+        assert_eq!(quantize_orientation(orientation(0.0, 1.0)), 90);
+        assert_eq!(quantize_orientation(orientation(0.0, -1.0)), 90);
+        assert_eq!(quantize_orientation(orientation(1.0, 1.0)), 45);
+        assert_eq!(quantize_orientation(orientation(-1.0, -1.0)), 45);
+        assert_eq!(quantize_orientation(orientation(-1.0, 1.0)), 135);
+        assert_eq!(quantize_orientation(orientation(1.0, -1.0)), 135);
+        assert!((0.0..180.0).contains(&orientation(1.0, -1e-9)));
+    }
+
+    #[test]
+    fn sobel_orientation_of_negative_angles() {
+        // Bright top right: the gradient points up and to the right, i.e. -45 degrees.
+        let direction = |image: &GrayImage, x, y| {
+            let (_, direction) = sobel_operator(image, &Kernel::Sobel3.operators(), BorderMode::Replicate);
+            quantize_orientation(direction.get_pixel(x, y).0[0])
+        };
+        assert_eq!(direction(&step_edge(1.0, -1.0, 0.0), 16, 16), 135);
+
+        // Bright bottom left: the gradient points down and to the left, i.e. 135 degrees.
+        assert_eq!(direction(&step_edge(-1.0, 1.0, 0.0), 16, 16), 135);
+
+        // Bright top left: the gradient points up and to the left, i.e. -135 degrees.
+        assert_eq!(direction(&step_edge(-1.0, -1.0, -(SIZE as f32)), 16, 15), 45);
+    }
+
+    #[test]
+    fn diagonal_edges_stay_thin() {
+        for suppression in [Suppression::Quantized, Suppression::Interpolated] {
+            // Main diagonal, bright on either side.
+            for sign in [1.0, -1.0] {
+                let edges = canny(&step_edge(sign, -sign, 0.0), &params(suppression)).unwrap();
+                assert_thin_edge(&edges, |y| y as f32);
+            }
+            // Anti-diagonal, bright on either side.
+            for sign in [1.0, -1.0] {
+                let c = sign * SIZE as f32;
+                let edges = canny(&step_edge(sign, sign, c), &params(suppression)).unwrap();
+                assert_thin_edge(&edges, |y| SIZE as f32 - y as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn every_kernel_finds_diagonal_edges() {
+        let kernels = [Kernel::Sobel3, Kernel::Sobel5, Kernel::Sobel7, Kernel::Scharr, Kernel::Prewitt, Kernel::RobertsCross];
+        for kernel in kernels {
+            for sign in [1.0, -1.0] {
+                let params = CannyParams { kernel: kernel.clone(), ..params(Suppression::Quantized) };
+                let edges = canny(&step_edge(sign, -sign, 0.0), &params).unwrap();
+                assert_thin_edge(&edges, |y| y as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn interpolated_suppression_follows_shallow_edges() {
+        // Edge along x = 2y - 16, roughly 63 degrees from horizontal.
+        let edges = canny(&step_edge(1.0, -2.0, -16.0), &params(Suppression::Interpolated)).unwrap();
+        for y in 10..SIZE / 2 + 6 {
+            let pixels = edge_pixels(&edges, y);
+            assert!(!pixels.is_empty(), "row {y} lost its edge");
+            for x in pixels {
+                assert!((x as f32 - (2.0 * y as f32 - 16.0)).abs() <= 2.0, "row {y}: edge pixel at {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_inverted_thresholds() {
+        let params = CannyParams { low_threshold: 0.5, high_threshold: 0.2, ..CannyParams::default() };
+        assert!(matches!(canny(&step_edge(1.0, 0.0, 16.0), &params), Err(Error::InvalidParameter(_))));
+    }
+
+    /// A strong pixel starting a weak run in row 2, an isolated weak run in row 6 and a
+    /// pixel below every threshold in row 9.
+    fn hysteresis_input() -> ResponseImage {
+        ResponseImage::from_fn(12, 12, |x, y| {
+            Luma([match (x, y) {
+                (2, 2) => 200.0,
+                (3..=7, 2) | (2..=7, 6) => 100.0,
+                (5, 9) => 40.0,
+                _ => 0.0,
+            }])
+        })
+    }
+
+    fn edge_set(edges: &GrayImage) -> Vec<(u32, u32)> {
+        edges.enumerate_pixels().filter(|(_, _, p)| p.0[0] == 255).map(|(x, y, _)| (x, y)).collect()
+    }
+
+    #[test]
+    fn hysteresis_keeps_weak_edges_connected_to_strong_ones() {
+        let edges = hysteresis(&hysteresis_input(), 0.3, 0.6);
+        assert_eq!(edge_set(&edges), (2..=7).map(|x| (x, 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn hysteresis_with_equal_thresholds_is_a_single_threshold() {
+        let edges = hysteresis(&hysteresis_input(), 0.3, 0.3);
+        let expected: Vec<_> = (2..=7).map(|x| (x, 2)).chain((2..=7).map(|x| (x, 6))).collect();
+        assert_eq!(edge_set(&edges), expected);
+
+        let edges = hysteresis(&hysteresis_input(), 0.5, 0.5);
+        assert_eq!(edge_set(&edges), vec![(2, 2)]);
+    }
 }
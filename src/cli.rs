@@ -0,0 +1,463 @@
+//! Command line: `serve` runs the HTTP server, `detect` runs detectors on image files.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Instant,
+};
+
+use rust_server_playground::{image_io, parallel, Error, Result};
+
+use crate::{
+    apply_params,
+    config::{self, Config, Settings},
+    keypoints_json, Decoded, Detector, Params, DETECTORS, PARAMETERS,
+};
+
+pub const USAGE: &str = "\
+Usage:
+  rust_server_playground [serve] [--config <file>] [options]
+  rust_server_playground detect <canny|sobel|harris|shi|all> [options] <input>... [-o <path>]
+
+Serve options (also CV_<NAME> environment variables and keys of a TOML or JSON config file):
+  --host <address>         Address to bind (default 127.0.0.1, 0.0.0.0 for all interfaces).
+  --port <n>               Port to listen on (default 8080).
+  --workers <n>            Connections handled concurrently (default: one per core).
+  --queue <n>              Connections waiting for a worker before answering 503 (default 64).
+  --threads <n>            Threads per detector (default: the cores divided among the
+                           requests running at the time).
+  --max-body-size <size>   Largest request body, e.g. 512K or 64M (default 64M).
+  --max-pixels <n>         Largest decoded image as width times height (default 50000000).
+  --read-timeout <s>       Seconds a client may stay silent while sending a request (default 30).
+  --idle-timeout <s>       Seconds a kept-alive connection waits for its next request, 0 to
+                           close after every response (default 5).
+  --static-dir <dir>       Directory of the web client (default src/client).
+  --cors-origins <list>    Origins of other frontends allowed to call the API, comma separated,
+                           or * for any origin without cookies (default: none).
+  --log-level <level>      error, warn, info or debug (default info).
+  --<parameter> <v>        Default of any detector parameter, e.g. --sigma 1.4 --kernel scharr.
+
+Inputs are image files, directories (every image directly inside) or patterns with
+`*` and `?` in the file name, e.g. 'photos/*.jpg'.
+
+Detect options:
+  -o, --out <path>     Output file for a single input and detector, otherwise a directory.
+                       Defaults to <input stem>_<detector>.png next to each input.
+  -j, --jobs <n>       Images processed at the same time (default: one per core).
+  --threads <n>        Threads per image (default: cores divided by jobs).
+  --max-pixels <n>     Largest image as width times height (default 50000000).
+  --<parameter> <v>    Any detector parameter of the HTTP API, e.g. --sigma 1.4 --low 0.1
+                       --high 0.3 --kernel scharr --border reflect101 --max-corners 100
+                       --output both --subpixel --overlay. Keypoints are written as JSON.";
+
+/// Extensions of the files picked up from directories and patterns.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "bmp", "gif", "tif", "tiff", "webp", "pnm", "pgm", "ppm", "tga"];
+
+/// Detector parameters that may be given without a value.
+const SWITCHES: &[&str] = &["subpixel", "overlay"];
+
+pub enum Command {
+    Serve(Config),
+    Detect(DetectOptions),
+    Help,
+}
+
+pub struct DetectOptions {
+    detectors: Vec<(&'static str, Detector)>,
+    params: Params,
+    inputs: Vec<PathBuf>,
+    out: Option<PathBuf>,
+    jobs: Option<usize>,
+    threads: Option<usize>,
+    max_pixels: Option<u64>,
+}
+
+/// Parses the arguments after the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> std::result::Result<Command, String> {
+    let args: Vec<String> = args.into_iter().collect();
+    match args.first().map(String::as_str) {
+        Some("-h" | "--help" | "help") => Ok(Command::Help),
+        Some("serve") => parse_serve(&args[1..]).map(Command::Serve),
+        Some("detect") => parse_detect(&args[1..]).map(Command::Detect),
+        // `port 8080 workers 8` without a subcommand still starts the server.
+        _ => parse_serve(&args).map(Command::Serve),
+    }
+}
+
+fn parse_serve(args: &[String]) -> std::result::Result<Config, String> {
+    let mut flags = Settings::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // The dashes are optional, so `port 8080` works as well as `--port 8080` or `--port=8080`.
+        let flag = arg.trim_start_matches("--");
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, value.to_string()),
+            None => (flag, args.next().ok_or_else(|| format!("{arg} needs a value"))?.clone()),
+        };
+        flags.set(name, value)?;
+    }
+
+    config::load(flags)
+}
+
+fn parse_detect(args: &[String]) -> std::result::Result<DetectOptions, String> {
+    let Some((algorithm, args)) = args.split_first() else {
+        return Err("detect needs a detector".into());
+    };
+    let detectors: Vec<(&'static str, Detector)> = match algorithm.as_str() {
+        "all" => DETECTORS.to_vec(),
+        name => match DETECTORS.iter().find(|(detector, _)| *detector == name) {
+            Some(&detector) => vec![detector],
+            None => return Err(format!("Unknown detector {name:?}, expected canny, sobel, harris, shi or all")),
+        },
+    };
+
+    let mut fields = HashMap::new();
+    let mut patterns = Vec::new();
+    let (mut out, mut jobs, mut threads, mut max_pixels) = (None, None, None, None);
+
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-').filter(|flag| flag.len() == 1)) else {
+            patterns.push(arg.clone());
+            continue;
+        };
+        let (flag, inline) = match flag.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (flag, None),
+        };
+        let name = flag.replace('-', "_");
+
+        let switch = SWITCHES.contains(&name.as_str());
+        let value = match inline {
+            Some(value) => value,
+            // A switch only takes the next argument when it is an explicit true or false.
+            None if switch && !matches!(args.peek().map(|v| v.as_str()), Some("true" | "false")) => "true".into(),
+            None => args.next().ok_or_else(|| format!("{arg} needs a value"))?.clone(),
+        };
+
+        match name.as_str() {
+            "o" | "out" => out = Some(PathBuf::from(value)),
+            "j" | "jobs" => jobs = Some(number(&value).filter(|&n| n > 0).ok_or("jobs must be a positive number")?),
+            "threads" => threads = Some(number(&value).ok_or("threads must be a number")?),
+            "max_pixels" => {
+                max_pixels = Some(number(&value).filter(|&n| n > 0).ok_or("max-pixels must be a positive number")? as u64);
+            }
+            name if PARAMETERS.contains(&name) => {
+                fields.insert(name.to_string(), value);
+            }
+            _ => return Err(format!("Unknown detect option {arg:?}")),
+        }
+    }
+
+    let params = apply_params(|name| fields.get(name), Params::default()).map_err(|e| e.to_string())?;
+    if patterns.is_empty() {
+        return Err("detect needs at least one input".into());
+    }
+    let mut inputs = Vec::new();
+    for pattern in &patterns {
+        inputs.extend(expand(pattern).map_err(|e| format!("{pattern}: {e}"))?);
+    }
+
+    let options = DetectOptions { detectors, params, inputs, out, jobs, threads, max_pixels };
+    check_outputs(&options)?;
+    Ok(options)
+}
+
+/// Fails when two inputs would write the same output, such as `photos/a.png` and
+/// `scans/a.tif` into one `-o` directory, rather than letting one overwrite the other.
+fn check_outputs(options: &DetectOptions) -> std::result::Result<(), String> {
+    let Some(&(detector, _)) = options.detectors.first() else {
+        return Ok(());
+    };
+    let mut outputs: HashMap<PathBuf, &Path> = HashMap::new();
+    for input in &options.inputs {
+        let output = output_path(options, input, detector);
+        if let Some(other) = outputs.insert(output.clone(), input) {
+            return Err(format!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                input.display(),
+                output.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn number(value: &str) -> Option<usize> {
+    value.trim().parse().ok()
+}
+
+/// The files an input argument stands for: the file itself, the images inside a
+/// directory, or the images whose name matches a `*` / `?` pattern. Directories and
+/// patterns skip the outputs of earlier runs, see [`is_output`].
+fn expand(input: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(input);
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+
+    let (directory, pattern) = if path.is_dir() {
+        (path, None)
+    } else if name.contains(['*', '?']) {
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        (parent, Some(name))
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    let mut files: Vec<PathBuf> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file() && !is_output(file))
+        .filter(|file| {
+            let name = file.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            match pattern {
+                Some(pattern) => wildcard_match(pattern, name),
+                None => file
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str())),
+            }
+        })
+        .collect();
+    if files.is_empty() {
+        return Err(Error::InvalidParameter("no images found".into()));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether `file` is named like an output of this command, `<stem>_<detector>.png`.
+fn is_output(file: &Path) -> bool {
+    let stem = file.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    file.extension().is_some_and(|extension| extension == "png")
+        && stem.rsplit_once('_').is_some_and(|(_, detector)| DETECTORS.iter().any(|(name, _)| *name == detector))
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters and `?` one character.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it is currently matched up to.
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character.
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Runs the detectors on every input, `jobs` images at a time, and returns the exit code.
+pub fn detect(options: DetectOptions) -> i32 {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let jobs = options.jobs.unwrap_or(cores).min(options.inputs.len());
+    parallel::set_threads(options.threads.unwrap_or((cores / jobs).max(1)));
+    if let Some(max_pixels) = options.max_pixels {
+        image_io::set_max_pixels(max_pixels);
+    }
+
+    if let Some(out) = options.out.as_ref().filter(|_| !out_is_file(&options)) {
+        if let Err(e) = fs::create_dir_all(out) {
+            eprintln!("{}: {e}", out.display());
+            return 1;
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let failures = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                while let Some(input) = options.inputs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if let Err(e) = process(&options, input) {
+                        eprintln!("{}: {e}", input.display());
+                        failures.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+
+    let failures = failures.into_inner();
+    if failures > 0 {
+        eprintln!("{failures} of {} images failed", options.inputs.len());
+        return 1;
+    }
+    0
+}
+
+/// Runs the selected detectors on one image and writes their outputs.
+fn process(options: &DetectOptions, input: &Path) -> Result<()> {
+    let now = Instant::now();
+    let bytes = fs::read(input)?;
+    let decoded = Decoded::new(&bytes, options.params.sigma, options.params.border)?;
+
+    let mut written = Vec::new();
+    for (name, detector) in &options.detectors {
+        let detection = detector(&decoded, &options.params)?;
+        let path = output_path(options, input, name);
+
+        // Keypoints go to a file of the image's name ending in `.json`, unless `-o` names
+        // the keypoints file itself.
+        let keypoints_path = match detection.image {
+            Some(image) => {
+                image.save(&path)?;
+                let keypoints_path = path.with_extension("json");
+                written.push(path);
+                keypoints_path
+            }
+            None if out_is_file(options) => path,
+            None => path.with_extension("json"),
+        };
+        if let Some(keypoints) = detection.keypoints {
+            fs::write(&keypoints_path, keypoints_json(&keypoints).to_string())?;
+            written.push(keypoints_path);
+        }
+    }
+
+    let written: Vec<String> = written.iter().map(|path| path.display().to_string()).collect();
+    println!("{} -> {} ({:.2?})", input.display(), written.join(", "), now.elapsed());
+    Ok(())
+}
+
+/// Whether `-o` names the output file rather than a directory.
+fn out_is_file(options: &DetectOptions) -> bool {
+    let single = options.inputs.len() == 1 && options.detectors.len() == 1;
+    single && options.out.as_ref().is_some_and(|out| !out.is_dir())
+}
+
+/// Where a detector's output for `input` goes: `-o` itself when it names a file,
+/// otherwise `<stem>_<detector>.png` in `-o` or next to the input.
+fn output_path(options: &DetectOptions, input: &Path, detector: &str) -> PathBuf {
+    let directory = match &options.out {
+        Some(out) if out_is_file(options) => return out.clone(),
+        Some(out) => out.as_path(),
+        None => input.parent().unwrap_or(Path::new(".")),
+    };
+    let stem = input.file_stem().and_then(|stem| stem.to_str()).unwrap_or("image");
+    directory.join(format!("{stem}_{detector}.png"))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    /// An empty directory unique to the test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("cv-cli-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn wildcards_match_runs_and_single_characters() {
+        assert!(wildcard_match("*.png", "a.png"));
+        assert!(wildcard_match("*.png", ".png"));
+        assert!(wildcard_match("img_??.tif", "img_01.tif"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("**x", "x"));
+        assert!(wildcard_match("é?.png", "éa.png"));
+
+        assert!(!wildcard_match("*.png", "a.pngx"));
+        assert!(!wildcard_match("img_??.tif", "img_1.tif"));
+        assert!(!wildcard_match("a*b*c", "aXbY"));
+        assert!(!wildcard_match("?", ""));
+        assert!(!wildcard_match("a.png", "A.png"));
+    }
+
+    #[test]
+    fn inputs_expand_to_files_directories_and_patterns() {
+        let dir = temp_dir("expand");
+        for name in ["b.png", "a.PNG", "c.tif", "notes.txt", "b_canny.png", "b_shi.png", "b_shi.json"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        fs::create_dir(dir.join("nested.png")).unwrap();
+
+        assert_eq!(expand(dir.to_str().unwrap()).unwrap(), [dir.join("a.PNG"), dir.join("b.png"), dir.join("c.tif")]);
+        assert_eq!(expand(dir.join("*.png").to_str().unwrap()).unwrap(), [dir.join("b.png")]);
+        assert_eq!(expand(dir.join("?.*").to_str().unwrap()).unwrap(), [dir.join("a.PNG"), dir.join("b.png"), dir.join("c.tif")]);
+        // Plain files are taken as they are, so a missing one fails when it is read.
+        assert_eq!(expand("missing.png").unwrap(), [PathBuf::from("missing.png")]);
+        // Outputs of earlier runs are only taken when named explicitly.
+        assert!(expand(dir.join("*_canny.png").to_str().unwrap()).is_err());
+        let output = dir.join("b_canny.png");
+        assert_eq!(expand(output.to_str().unwrap()).unwrap(), [output]);
+        assert!(expand(dir.join("*.jpg").to_str().unwrap()).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn inputs_writing_the_same_output_are_rejected() {
+        let dir = temp_dir("collisions");
+        for sub in ["photos", "scans"] {
+            fs::create_dir(dir.join(sub)).unwrap();
+        }
+        let photo = dir.join("photos/a.png");
+        let scan = dir.join("scans/a.tif");
+        let other = dir.join("scans/b.tif");
+        for file in [&photo, &scan, &other] {
+            fs::write(file, b"").unwrap();
+        }
+        let out = dir.join("results");
+        let (photo, scan, other, out) =
+            (photo.to_str().unwrap(), scan.to_str().unwrap(), other.to_str().unwrap(), out.to_str().unwrap());
+
+        let error = parse_detect(&args(&["canny", photo, scan, "-o", out])).err().unwrap();
+        assert!(error.contains("a_canny.png"), "{error}");
+        assert!(parse_detect(&args(&["all", photo, other, "-o", out])).is_ok());
+        // Next to their inputs, the outputs of both directories stay apart.
+        assert!(parse_detect(&args(&["canny", photo, scan])).is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keypoints_alone_are_written_as_json() {
+        let dir = temp_dir("keypoints");
+        let input = dir.join("square.png");
+        GrayImage::from_fn(40, 40, |x, y| Luma([if (10..30).contains(&x) && (10..30).contains(&y) { 200 } else { 20 }]))
+            .save(&input)
+            .unwrap();
+
+        let options = parse_detect(&args(&["harris", "--output", "keypoints", input.to_str().unwrap()])).unwrap();
+        process(&options, &input).unwrap();
+
+        assert!(!dir.join("square_harris.png").exists());
+        let keypoints: serde_json::Value = serde_json::from_slice(&fs::read(dir.join("square_harris.json")).unwrap()).unwrap();
+        let keypoints = keypoints.as_array().unwrap();
+        assert_eq!(keypoints.len(), 4, "{keypoints:?}");
+        assert!(keypoints.iter().all(|keypoint| keypoint["score"].as_f64().is_some_and(|score| score > 0.0)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
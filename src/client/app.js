@@ -28,8 +28,6 @@ image_input.addEventListener("change", async () => {
         let imageBox = document.createElement("div")
 
         let file = image_input.files[0]
-        let imageData = new FormData()
-        imageData.append("file", file);
 
         let reader = new FileReader();
         reader.onload = (evt) => {
@@ -112,85 +110,60 @@ async function setThreshold(threshold) {
     return r1
 }
 
-async function sobel(image, sigma) {
-    let r1 = await setSigma(sigma)
-    if(r1.ok) {
-        let res = await fetch("/sobel", {
-            method: "POST",
-            headers: {
-                "Content-length": image.length
-            },
-            body: image
-        });
-        return await res.json()
-    } else {
-        return null
+function formData(image, sigma, threshold) {
+    let data = new FormData()
+    data.append("file", image)
+    if(sigma !== undefined) {
+        data.append("sigma", sigma)
+    }
+    if(threshold !== undefined) {
+        data.append("threshold", threshold)
     }
+    return data
+}
+
+async function sobel(image, sigma) {
+    let res = await fetch("/sobel", {
+        method: "POST",
+        body: formData(image, sigma)
+    });
+    return await res.json()
 }
 
 async function canny(image, sigma, threshold) {
-    let r1 = await setSigma(sigma)
-    let r2 = await setThreshold(threshold)
-
-    if(r1.ok && r2.ok) {
-        let res = await fetch("/canny", {
-            method: "POST",
-            headers: {
-                "Content-length": image.length
-            },
-            body: image
-        });
-        
-        return await res.json()
-    } else {
-        return null
-    }
+    let res = await fetch("/canny", {
+        method: "POST",
+        body: formData(image, sigma, threshold)
+    });
+    return await res.json()
 }
 
 async function harris(image) {
     let res = await fetch("/harris", {
         method: "POST",
-        headers: {
-            "Content-length": image.length
-        },
-        body: image
+        body: formData(image)
     })
-
     return await res.json();
 }
 
+// Shi-Tomasi keeps corners scoring at least `quality_level` times the strongest one,
+// so the threshold input is sent under that name.
 async function shi(image, threshold) {
-    let res1 = await setThreshold(threshold)
-    if(res1.ok) {
-        let res = await fetch("/shi", {
-            method: "POST",
-            headers: {
-                "Content-length": image.length
-            },
-            body: image
-        })
-        return await res.json();
-    } else {
-        return null;
-    }
-
+    let data = formData(image)
+    data.append("quality_level", threshold)
+    let res = await fetch("/shi", {
+        method: "POST",
+        body: data
+    })
+    return await res.json();
 }
 
 async function all(image, sigma, threshold) {
-    let r1 = await setSigma(sigma)
-    let r2 = await setThreshold(threshold)
-
-    if(r1.ok && r2.ok) {
-        let res = await fetch("/all", {
-            method: "POST",
-            headers: {
-                "Content-length": image.length
-            },
-            body: image
-        });
-        
-        return await res.json()
-    } else {
-        return null
-    }
-}
\ No newline at end of file
+    let data = formData(image, sigma, threshold)
+    data.append("quality_level", threshold)
+    let res = await fetch("/all", {
+        method: "POST",
+        body: data
+    });
+    return await res.json()
+}
@@ -0,0 +1,299 @@
+//! Server configuration, layered from lowest to highest priority: built-in defaults, a
+//! TOML or JSON config file, `CV_*` environment variables and command line flags.
+
+use std::{collections::HashMap, env, fs, path::PathBuf, thread, time::Duration};
+
+use rust_server_playground::image_io;
+use serde_json::Value;
+
+use crate::{apply_params, cors::Cors, logger::Level, Params, PARAMETERS};
+
+/// Prefix of the environment variables, e.g. `CV_PORT` or `CV_SIGMA`.
+const ENV_PREFIX: &str = "CV_";
+
+/// Server settings, next to the detector parameters of [`PARAMETERS`].
+const SERVER_KEYS: &[&str] = &[
+    "host", "port", "workers", "queue", "threads", "max_body_size", "max_pixels", "read_timeout", "idle_timeout", "static_dir",
+    "cors_origins", "log_level",
+];
+
+/// Fully resolved server configuration.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    /// Connections handled concurrently.
+    pub workers: usize,
+    /// Connections waiting for a worker before the server answers 503.
+    pub queue: usize,
+    /// Threads each detector splits its work across; `None` divides the cores among the
+    /// requests running at the time.
+    pub threads: Option<usize>,
+    /// Largest accepted request body in bytes.
+    pub max_body_size: usize,
+    /// Largest decoded image, as width times height.
+    pub max_pixels: u64,
+    /// How long a connection may stay silent while its request is read.
+    pub read_timeout: Duration,
+    /// How long a kept-alive connection may wait for its next request; zero closes every
+    /// connection after one request.
+    pub idle_timeout: Duration,
+    /// Directory the web client is served from.
+    pub static_dir: PathBuf,
+    /// Origins of other frontends allowed to call the API.
+    pub cors: Cors,
+    pub log_level: Level,
+    /// Detector parameters used when neither the request nor the session sets them.
+    pub defaults: Params,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "127.0.0.1".into(),
+            port: 8080,
+            workers: thread::available_parallelism().map_or(4, |n| n.get()),
+            queue: 64,
+            threads: None,
+            max_body_size: 64 * 1024 * 1024,
+            max_pixels: image_io::DEFAULT_MAX_PIXELS,
+            read_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(5),
+            static_dir: PathBuf::from("src/client"),
+            cors: Cors::default(),
+            log_level: Level::Info,
+            defaults: Params::default(),
+        }
+    }
+}
+
+/// Raw `name = value` settings from one source, before they are validated.
+#[derive(Debug, Default)]
+pub struct Settings {
+    server: HashMap<String, String>,
+    detector: HashMap<String, String>,
+    /// Config file to read, only taken from flags and the environment.
+    config: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Stores one setting; names may use dashes instead of underscores.
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        let name = name.replace('-', "_");
+        match name.as_str() {
+            "config" => self.config = Some(PathBuf::from(value)),
+            name if SERVER_KEYS.contains(&name) => {
+                self.server.insert(name.to_string(), value);
+            }
+            name if PARAMETERS.contains(&name) => {
+                self.detector.insert(name.to_string(), value);
+            }
+            _ => return Err(format!("Unknown setting {name:?}")),
+        }
+        Ok(())
+    }
+
+    /// Settings of `other` replace the ones already set.
+    fn merge(&mut self, other: Settings) {
+        self.server.extend(other.server);
+        self.detector.extend(other.detector);
+        self.config = other.config.or(self.config.take());
+    }
+
+    /// `CV_<NAME>` variables for every known setting and `CV_CONFIG`.
+    fn from_env() -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        for name in SERVER_KEYS.iter().chain(PARAMETERS).chain(&["config"]) {
+            if let Ok(value) = env::var(format!("{ENV_PREFIX}{}", name.to_uppercase())) {
+                settings.set(name, value)?;
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Top-level server settings and a `detector` table of detector parameters. Files
+    /// ending in `.json` are JSON, everything else TOML.
+    fn from_file(path: &PathBuf) -> Result<Settings, String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+        let contents = fs::read_to_string(path).map_err(|e| error(&e))?;
+
+        let document: Value = if path.extension().is_some_and(|extension| extension == "json") {
+            serde_json::from_str(&contents).map_err(|e| error(&e))?
+        } else {
+            let table: toml::Table = contents.parse().map_err(|e| error(&e))?;
+            serde_json::to_value(table).map_err(|e| error(&e))?
+        };
+        let Value::Object(document) = document else {
+            return Err(error(&"the config must be an object"));
+        };
+
+        // Strings are taken as they are, numbers, booleans and kernel arrays as JSON.
+        let text = |value: Value| match value {
+            Value::String(value) => value,
+            value => value.to_string(),
+        };
+        let mut settings = Settings::default();
+        for (name, value) in document {
+            match (name.as_str(), value) {
+                ("detector", Value::Object(parameters)) => {
+                    for (name, value) in parameters {
+                        if !PARAMETERS.contains(&name.replace('-', "_").as_str()) {
+                            return Err(error(&format!("unknown detector parameter {name:?}")));
+                        }
+                        settings.set(&name, text(value)).map_err(|e| error(&e))?;
+                    }
+                }
+                ("config", _) => return Err(error(&"a config file cannot include another")),
+                (name, value) => settings.set(name, text(value)).map_err(|e| error(&e))?,
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// Resolves the configuration from the config file, the environment and `flags`.
+pub fn load(flags: Settings) -> Result<Config, String> {
+    let env = Settings::from_env()?;
+    let file = match flags.config.as_ref().or(env.config.as_ref()) {
+        Some(path) => Settings::from_file(path)?,
+        None => Settings::default(),
+    };
+
+    let mut settings = file;
+    settings.merge(env);
+    settings.merge(flags);
+
+    let mut config = Config::default();
+    for (name, value) in &settings.server {
+        let number = || value.trim().parse::<usize>().map_err(|_| format!("{name} must be a number, got {value:?}"));
+        match name.as_str() {
+            "host" => config.host = value.trim().to_string(),
+            "port" => config.port = value.trim().parse().map_err(|_| format!("port must be a port number, got {value:?}"))?,
+            "workers" => config.workers = number().and_then(positive(name))?,
+            "queue" => config.queue = number()?,
+            "threads" => config.threads = Some(number()?),
+            "max_body_size" => config.max_body_size = parse_size(value).and_then(positive(name))?,
+            "max_pixels" => config.max_pixels = number().and_then(positive(name))? as u64,
+            "read_timeout" => config.read_timeout = Duration::from_secs(number().and_then(positive(name))? as u64),
+            "idle_timeout" => config.idle_timeout = Duration::from_secs(number()? as u64),
+            "static_dir" => config.static_dir = PathBuf::from(value),
+            "cors_origins" => config.cors = Cors::parse(value)?,
+            "log_level" => {
+                config.log_level = Level::parse(value.trim())
+                    .ok_or_else(|| format!("log_level must be error, warn, info or debug, got {value:?}"))?;
+            }
+            _ => unreachable!("only known settings are stored"),
+        }
+    }
+    config.defaults = apply_params(|name| settings.detector.get(name), config.defaults).map_err(|e| e.to_string())?;
+
+    Ok(config)
+}
+
+fn positive(name: &str) -> impl Fn(usize) -> Result<usize, String> + '_ {
+    move |value| if value > 0 { Ok(value) } else { Err(format!("{name} must be positive")) }
+}
+
+/// Byte count with an optional `K`, `M` or `G` suffix (powers of 1024), e.g. `64M`.
+fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match value[digits.len()..].to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => 0,
+    };
+    digits
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|_| multiplier > 0)
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(|| format!("max_body_size must be a size like 1048576, 512K or 64M, got {value:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(values: &[(&str, &str)]) -> Settings {
+        let mut settings = Settings::default();
+        for (name, value) in values {
+            settings.set(name, value.to_string()).unwrap();
+        }
+        settings
+    }
+
+    /// Writes `contents` to a config file unique to the test.
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("cv-config-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn sizes_take_binary_suffixes() {
+        assert_eq!(parse_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size(" 64m "), Ok(64 << 20));
+        assert_eq!(parse_size("64MiB"), Ok(64 << 20));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+
+        for invalid in ["12X", "M", "", "-1K", "1.5M", "17179869184G", "99999999999999999999999"] {
+            assert!(parse_size(invalid).is_err(), "{invalid:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn later_sources_win() {
+        let file = settings(&[("port", "9000"), ("workers", "2"), ("sigma", "1.0"), ("config", "file.toml")]);
+        let env = settings(&[("port", "9001"), ("sigma", "2.0")]);
+        let flags = settings(&[("port", "9002"), ("max-body-size", "1M")]);
+
+        let mut merged = file;
+        merged.merge(env);
+        merged.merge(flags);
+
+        assert_eq!(merged.server["port"], "9002");
+        assert_eq!(merged.server["workers"], "2");
+        assert_eq!(merged.server["max_body_size"], "1M");
+        assert_eq!(merged.detector["sigma"], "2.0");
+        assert_eq!(merged.config, Some(PathBuf::from("file.toml")));
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(Settings::default().set("colour", "red".into()).is_err());
+        assert!(Settings::default().set("max-body-size", "1M".into()).is_ok());
+    }
+
+    #[test]
+    fn files_hold_server_settings_and_a_detector_table() {
+        let toml = config_file("valid.toml", "port = 9000\nhost = \"0.0.0.0\"\n\n[detector]\nsigma = 1.4\nkernel = \"scharr\"\n");
+        let settings = Settings::from_file(&toml).unwrap();
+        assert_eq!(settings.server["port"], "9000");
+        assert_eq!(settings.server["host"], "0.0.0.0");
+        assert_eq!(settings.detector["sigma"], "1.4");
+        assert_eq!(settings.detector["kernel"], "scharr");
+
+        let json = config_file("valid.json", r#"{"queue": 8, "detector": {"kernel_x": [[-1, 0, 1]]}}"#);
+        let settings = Settings::from_file(&json).unwrap();
+        assert_eq!(settings.server["queue"], "8");
+        assert_eq!(settings.detector["kernel_x"], "[[-1,0,1]]");
+
+        for (name, contents) in [
+            ("unknown.toml", "colour = \"red\"\n"),
+            ("server-in-detector.toml", "[detector]\nport = 9000\n"),
+            ("nested.toml", "config = \"other.toml\"\n"),
+            ("not-an-object.json", "[1, 2]"),
+        ] {
+            let path = config_file(name, contents);
+            assert!(Settings::from_file(&path).is_err(), "{name} was accepted");
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_file(toml).unwrap();
+        fs::remove_file(json).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! Convolution of row-major sample buffers with precomputed kernels.
+//!
+//! Kernels are applied the way the gradient operators are written, without flipping
+//! them. Rows are spread over threads with [`crate::parallel`], and pixels whose
+//! neighbourhood lies inside the image are summed straight from the row slices; only
+//! the frame around the border goes through [`BorderMode`].
+
+use crate::{border::BorderMode, parallel};
+
+/// One non-zero weight at an offset from the pixel being computed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Tap {
+    dx: isize,
+    dy: isize,
+    weight: f32,
+}
+
+/// A 2D kernel, precomputed into its non-zero taps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    taps: Vec<Tap>,
+    /// Columns and rows the kernel reaches before the pixel, i.e. left and up.
+    before: (usize, usize),
+    /// Columns and rows the kernel reaches after the pixel, i.e. right and down.
+    after: (usize, usize),
+}
+
+impl Filter {
+    /// Kernel from weights indexed `[row][column]`, i.e. `[dy][dx]`. Odd sizes are centred
+    /// on the pixel, even ones reach one further right and down. All rows must have the
+    /// same, non-zero length.
+    pub fn new(weights: &[Vec<f32>]) -> Filter {
+        let columns = weights.first().map_or(0, Vec::len);
+        assert!(columns > 0 && weights.iter().all(|row| row.len() == columns), "kernel must be a non-empty rectangle");
+
+        let before = ((columns - 1) / 2, (weights.len() - 1) / 2);
+        let after = (columns - 1 - before.0, weights.len() - 1 - before.1);
+        let taps = weights
+            .iter()
+            .enumerate()
+            .flat_map(|(row, weights)| weights.iter().enumerate().map(move |(column, &weight)| (row, column, weight)))
+            .filter(|&(_, _, weight)| weight != 0.0)
+            .map(|(row, column, weight)| Tap {
+                dx: column as isize - before.0 as isize,
+                dy: row as isize - before.1 as isize,
+                weight,
+            })
+            .collect();
+
+        Filter { taps, before, after }
+    }
+}
+
+/// Convolution of a row-major `width` x `height` buffer with `filter`. With
+/// [`BorderMode::Skip`], pixels whose neighbourhood leaves the image are 0.
+pub fn convolve<T>(source: &[T], width: usize, height: usize, filter: &Filter, border: BorderMode) -> Vec<f32>
+where
+    T: Copy + Into<f32> + Sync,
+{
+    assert_eq!(source.len(), width * height, "buffer must be width x height");
+    let ((left, top), (right, bottom)) = (filter.before, filter.after);
+
+    let mut target = vec![0.0; source.len()];
+    parallel::for_each_row(&mut target, width, |y, row| {
+        // Columns whose whole neighbourhood lies inside the image, if any.
+        let inner = if y >= top && y + bottom < height && left + right < width { left..width - right } else { 0..0 };
+
+        if !inner.is_empty() {
+            for tap in &filter.taps {
+                let start = ((y as isize + tap.dy) * width as isize + inner.start as isize + tap.dx) as usize;
+                for (value, &sample) in row[inner.clone()].iter_mut().zip(&source[start..start + inner.len()]) {
+                    *value += tap.weight * sample.into();
+                }
+            }
+        }
+
+        if border == BorderMode::Skip {
+            // The frame stays 0; zero weights count too, the neighbourhood is the whole kernel.
+            return;
+        }
+        for (x, value) in row.iter_mut().enumerate().filter(|(x, _)| !inner.contains(x)) {
+            *value = filter
+                .taps
+                .iter()
+                .map(|tap| {
+                    let (x, y) = (x as i64 + tap.dx as i64, y as i64 + tap.dy as i64);
+                    Some(tap.weight * sample(source, width, height, x, y, border)?)
+                })
+                .sum::<Option<f32>>()
+                .unwrap_or(0.0);
+        }
+    });
+
+    target
+}
+
+/// Sample at a position that may lie outside the buffer, or `None` when it is skipped.
+fn sample<T: Copy + Into<f32>>(source: &[T], width: usize, height: usize, x: i64, y: i64, border: BorderMode) -> Option<f32> {
+    match (border.index(x, width), border.index(y, height), border) {
+        (Some(x), Some(y), _) => Some(source[y * width + x].into()),
+        (_, _, BorderMode::Constant(value)) => Some(value),
+        _ => None,
+    }
+}
+
+/// Convolution of a row-major `width` x `height` buffer with a separable kernel: each row
+/// with `row_kernel`, then each column of the result with `column_kernel`. Both kernels
+/// have odd lengths and are centred on the pixel.
+pub fn convolve_separable<T>(
+    source: &[T],
+    width: usize,
+    height: usize,
+    row_kernel: &[f32],
+    column_kernel: &[f32],
+    border: BorderMode,
+) -> Vec<f32>
+where
+    T: Copy + Into<f32> + Sync,
+{
+    assert_eq!(source.len(), width * height, "buffer must be width x height");
+    if source.is_empty() {
+        return Vec::new();
+    }
+
+    let mut rows = vec![0.0; source.len()];
+    parallel::for_each_row(&mut rows, width, |y, target| {
+        convolve_line(&source[y * width..(y + 1) * width], target, row_kernel, border);
+    });
+
+    // Rows above and below the image are constant rows that went through the row kernel too.
+    let column_border = match border {
+        BorderMode::Constant(value) => BorderMode::Constant(value * row_kernel.iter().sum::<f32>()),
+        border => border,
+    };
+    let mut convolved = vec![0.0; source.len()];
+    parallel::for_each_row(&mut convolved, width, |y, target| {
+        convolve_columns(&rows, y, height, target, column_kernel, column_border);
+    });
+
+    convolved
+}
+
+/// 1D convolution of one row with a centred kernel.
+fn convolve_line<T: Copy + Into<f32>>(source: &[T], target: &mut [f32], kernel: &[f32], border: BorderMode) {
+    let radius = kernel.len() / 2;
+    let len = source.len();
+
+    for (i, value) in target.iter_mut().enumerate() {
+        *value = if i >= radius && i + radius < len {
+            // The whole kernel fits, no border lookups needed.
+            source[i - radius..=i + radius].iter().zip(kernel).map(|(&s, k)| s.into() * k).sum()
+        } else {
+            // A skipped sample leaves the whole output pixel at 0.
+            kernel
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| Some(weight * sample(source, len, 1, i as i64 + k as i64 - radius as i64, 0, border)?))
+                .sum::<Option<f32>>()
+                .unwrap_or(0.0)
+        };
+    }
+}
+
+/// Row `y` of the column-wise convolution of a row-major buffer, accumulated a whole
+/// source row at a time instead of walking down each column.
+fn convolve_columns(source: &[f32], y: usize, height: usize, target: &mut [f32], kernel: &[f32], border: BorderMode) {
+    let radius = kernel.len() / 2;
+    let width = target.len();
+
+    for (k, &weight) in kernel.iter().enumerate() {
+        match (border.index(y as i64 + k as i64 - radius as i64, height), border) {
+            (Some(row), _) => {
+                for (value, sample) in target.iter_mut().zip(&source[row * width..(row + 1) * width]) {
+                    *value += weight * sample;
+                }
+            }
+            (None, BorderMode::Constant(sample)) => target.iter_mut().for_each(|value| *value += weight * sample),
+            (None, _) => {
+                // A skipped sample leaves the whole output pixel at 0.
+                target.fill(0.0);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [BorderMode; 6] = [
+        BorderMode::Replicate,
+        BorderMode::Reflect,
+        BorderMode::Reflect101,
+        BorderMode::Wrap,
+        BorderMode::Constant(3.0),
+        BorderMode::Skip,
+    ];
+
+    /// Straightforward per-pixel convolution to compare against.
+    fn reference(source: &[f32], width: usize, height: usize, weights: &[Vec<f32>], border: BorderMode) -> Vec<f32> {
+        let (left, top) = ((weights[0].len() - 1) / 2, (weights.len() - 1) / 2);
+        let mut target = vec![0.0; source.len()];
+        for y in 0..height {
+            'pixels: for x in 0..width {
+                let mut sum = 0.0;
+                for (row, weights) in weights.iter().enumerate() {
+                    for (column, weight) in weights.iter().enumerate() {
+                        let (sx, sy) = ((x + column) as i64 - left as i64, (y + row) as i64 - top as i64);
+                        let sample = match (border.index(sx, width), border.index(sy, height), border) {
+                            (Some(sx), Some(sy), _) => source[sy * width + sx],
+                            (_, _, BorderMode::Constant(value)) => value,
+                            _ => continue 'pixels,
+                        };
+                        sum += weight * sample;
+                    }
+                }
+                target[y * width + x] = sum;
+            }
+        }
+        target
+    }
+
+    #[test]
+    fn matches_per_pixel_convolution() {
+        let weights = vec![vec![1.0, -2.0, 0.0, 4.0], vec![0.0, 3.0, 1.0, -1.0], vec![2.0, 0.0, 0.0, 1.0]];
+        let filter = Filter::new(&weights);
+
+        for (width, height) in [(9, 7), (2, 2), (1, 5), (4, 3)] {
+            let source: Vec<f32> = (0..width * height).map(|i| ((i * 37) % 11) as f32).collect();
+            for border in MODES {
+                assert_eq!(
+                    convolve(&source, width, height, &filter, border),
+                    reference(&source, width, height, &weights, border),
+                    "{width}x{height} {border:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn separable_matches_the_outer_product() {
+        let (row_kernel, column_kernel) = ([1.0, 2.0, 1.0], [-1.0, 0.0, 1.0]);
+        let weights: Vec<Vec<f32>> = column_kernel.iter().map(|c| row_kernel.iter().map(|r| r * c).collect()).collect();
+
+        let (width, height) = (8, 6);
+        let source: Vec<u8> = (0..width * height).map(|i| ((i * 53) % 256) as u8).collect();
+        for border in MODES {
+            assert_eq!(
+                convolve_separable(&source, width, height, &row_kernel, &column_kernel, border),
+                convolve(&source, width, height, &Filter::new(&weights), border),
+                "{border:?}"
+            );
+        }
+    }
+}
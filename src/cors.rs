@@ -0,0 +1,155 @@
+//! Cross-origin access for frontends served from other origins.
+
+use crate::http::{Method, Request, Response};
+
+/// Methods the API answers, as listed in preflight responses.
+const ALLOWED_METHODS: &str = "GET, HEAD, POST";
+
+/// Seconds browsers may cache a preflight response.
+const MAX_AGE: u32 = 600;
+
+/// Origins allowed to call the server from a browser.
+#[derive(Clone, Debug, Default)]
+pub enum Cors {
+    /// Only same-origin requests, as browsers enforce without CORS headers.
+    #[default]
+    Disabled,
+    /// Any origin, without credentials.
+    AnyOrigin,
+    /// The listed origins, which may send the session cookie along.
+    Origins(Vec<String>),
+}
+
+impl Cors {
+    /// `*`, or origins separated by commas such as `https://a.example, http://localhost:3000`.
+    /// A JSON array of origins, as written in a config file, works too.
+    pub fn parse(value: &str) -> Result<Cors, String> {
+        let value = value.trim();
+        let origins: Vec<String> = if value.starts_with('[') {
+            serde_json::from_str(value).map_err(|_| format!("cors_origins must be a list of origins, got {value:?}"))?
+        } else {
+            value.split(',').map(|origin| origin.trim().to_string()).filter(|origin| !origin.is_empty()).collect()
+        };
+
+        if origins.is_empty() {
+            return Ok(Cors::Disabled);
+        }
+        if origins.iter().any(|origin| origin == "*") {
+            return Ok(Cors::AnyOrigin);
+        }
+        // Browsers send the origin without a trailing slash.
+        Ok(Cors::Origins(origins.into_iter().map(|origin| origin.trim_end_matches('/').to_string()).collect()))
+    }
+
+    /// The `Access-Control-Allow-Origin` value for the request's origin, if it is allowed.
+    fn allow_origin<'a>(&self, request: &'a Request) -> Option<&'a str> {
+        let origin = request.header("origin")?;
+        match self {
+            Cors::Disabled => None,
+            Cors::AnyOrigin => Some("*"),
+            Cors::Origins(origins) => origins.iter().any(|allowed| allowed == origin).then_some(origin),
+        }
+    }
+
+    /// The answer to a preflight `OPTIONS` request from an allowed origin.
+    pub fn preflight(&self, request: &Request) -> Option<Response> {
+        if request.method != Method::Options || request.header("access-control-request-method").is_none() {
+            return None;
+        }
+        self.allow_origin(request)?;
+
+        let mut response = Response::new(204)
+            .with_header("Access-Control-Allow-Methods", ALLOWED_METHODS)
+            .with_header("Access-Control-Max-Age", &MAX_AGE.to_string());
+        if let Some(headers) = request.header("access-control-request-headers") {
+            response = response.with_header("Access-Control-Allow-Headers", headers);
+        }
+        Some(response)
+    }
+
+    /// Adds the `Access-Control-*` headers when the request comes from an allowed origin.
+    pub fn apply(&self, request: &Request, response: Response) -> Response {
+        match (self, self.allow_origin(request)) {
+            (Cors::Disabled, _) | (Cors::AnyOrigin, None) => response,
+            (Cors::AnyOrigin, Some(origin)) => response.with_header("Access-Control-Allow-Origin", origin),
+            // The answer depends on the origin, so caches must keep one per origin.
+            (Cors::Origins(_), Some(origin)) => response
+                .with_header("Vary", "Origin")
+                .with_header("Access-Control-Allow-Origin", origin)
+                .with_header("Access-Control-Allow-Credentials", "true"),
+            (Cors::Origins(_), None) => response.with_header("Vary", "Origin"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        let headers: String = headers.iter().map(|(name, value)| format!("{name}: {value}\r\n")).collect();
+        let raw = format!("{method} /canny HTTP/1.1\r\n{headers}\r\n");
+        Request::read(&mut raw.as_bytes(), 0).unwrap()
+    }
+
+    fn listed() -> Cors {
+        Cors::parse("http://localhost:3000, https://app.example/").unwrap()
+    }
+
+    #[test]
+    fn parses_lists_and_wildcards() {
+        assert!(matches!(Cors::parse(""), Ok(Cors::Disabled)));
+        assert!(matches!(Cors::parse("[]"), Ok(Cors::Disabled)));
+        assert!(matches!(Cors::parse("https://a.example, *"), Ok(Cors::AnyOrigin)));
+        let Ok(Cors::Origins(origins)) = Cors::parse(r#"["http://localhost:3000/", "https://a.example"]"#) else {
+            panic!("expected a list of origins");
+        };
+        assert_eq!(origins, ["http://localhost:3000", "https://a.example"]);
+        assert!(Cors::parse("[1]").is_err());
+    }
+
+    #[test]
+    fn apply_allows_only_listed_origins() {
+        let allowed = request("POST", &[("Origin", "https://app.example")]);
+        let response = listed().apply(&allowed, Response::new(200));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://app.example"));
+        assert_eq!(response.header("Access-Control-Allow-Credentials"), Some("true"));
+        assert_eq!(response.header("Vary"), Some("Origin"));
+
+        let other = request("POST", &[("Origin", "https://evil.example")]);
+        let response = listed().apply(&other, Response::new(200));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Vary"), Some("Origin"));
+
+        let response = Cors::AnyOrigin.apply(&other, Response::new(200));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+
+        let response = Cors::Disabled.apply(&allowed, Response::new(200));
+        assert!(response.headers.iter().all(|(name, _)| !name.starts_with("Access-Control")));
+        // Same-origin requests carry no Origin header and get nothing added.
+        assert_eq!(Cors::AnyOrigin.apply(&request("POST", &[]), Response::new(200)).headers.len(), 0);
+    }
+
+    #[test]
+    fn preflight_answers_allowed_origins_only() {
+        let preflight = |origin| {
+            request(
+                "OPTIONS",
+                &[("Origin", origin), ("Access-Control-Request-Method", "POST"), ("Access-Control-Request-Headers", "content-type")],
+            )
+        };
+
+        let response = listed().preflight(&preflight("http://localhost:3000")).unwrap();
+        assert_eq!(response.status, 204);
+        assert_eq!(response.header("Access-Control-Allow-Methods"), Some(ALLOWED_METHODS));
+        assert_eq!(response.header("Access-Control-Allow-Headers"), Some("content-type"));
+        assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+
+        assert!(listed().preflight(&preflight("https://evil.example")).is_none());
+        assert!(Cors::Disabled.preflight(&preflight("http://localhost:3000")).is_none());
+        // A plain OPTIONS request or another method is not a preflight.
+        assert!(listed().preflight(&request("OPTIONS", &[("Origin", "http://localhost:3000")])).is_none());
+        assert!(listed().preflight(&request("POST", &[("Origin", "http://localhost:3000")])).is_none());
+    }
+}
@@ -0,0 +1,69 @@
+use std::{fmt, io};
+
+use image::ImageError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The upload (image, multipart or JSON body) could not be decoded.
+    Decode(String),
+    UnsupportedFormat(String),
+    InvalidParameter(String),
+    Io(io::Error),
+    /// The body or the decoded image exceeds the configured limits.
+    TooLarge(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Short machine readable name, used in JSON error responses.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Decode(_) => "decode",
+            Error::UnsupportedFormat(_) => "unsupported_format",
+            Error::InvalidParameter(_) => "invalid_parameter",
+            Error::Io(_) => "io",
+            Error::TooLarge(_) => "too_large",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Decode(message) => write!(f, "Failed to decode: {message}"),
+            Error::UnsupportedFormat(message) => write!(f, "Unsupported format: {message}"),
+            Error::InvalidParameter(message) => write!(f, "Invalid parameter: {message}"),
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::TooLarge(message) => write!(f, "Too large: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(e: ImageError) -> Error {
+        match e {
+            ImageError::Decoding(e) => Error::Decode(e.to_string()),
+            ImageError::Unsupported(e) => Error::UnsupportedFormat(e.to_string()),
+            ImageError::Parameter(e) => Error::InvalidParameter(e.to_string()),
+            ImageError::Limits(e) => Error::TooLarge(e.to_string()),
+            ImageError::IoError(e) => Error::Io(e),
+            ImageError::Encoding(e) => Error::Io(io::Error::other(e.to_string())),
+        }
+    }
+}
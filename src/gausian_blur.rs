@@ -1,69 +1,124 @@
-use std::f32::consts::PI;
+use image::{GrayImage, ImageBuffer};
 
-use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+use crate::{
+    border::BorderMode,
+    convolution::convolve_separable,
+    error::{Error, Result},
+    ResponseImage,
+};
 
-#[no_mangle]
-pub fn apply_gausian_filter(image: DynamicImage, sigma: f32) -> Result<DynamicImage, String> {
-    let width = image.width() as i32;
-    let height = image.height() as i32;
+/// Largest accepted sigma. Its kernel already spans 601 pixels, and every pixel pays for
+/// each tap, so larger values would only tie up the caller.
+pub const MAX_SIGMA: f32 = 100.0;
 
-    let mask_size: usize = 5;
-    if mask_size % 2 == 0 {
-        return Err("Mask must be an odd number".into());
+/// Normalised 1D Gaussian weights for offsets `-radius..=radius`, with `radius = ceil(3 * sigma)`.
+pub fn gaussian_kernel(sigma: f32) -> Result<Vec<f32>> {
+    if !sigma.is_finite() || sigma <= 0.0 {
+        return Err(Error::InvalidParameter(format!("sigma must be a positive number, got {sigma}")));
     }
-    let k: i32 = ((mask_size - 1) / 2) as i32;
+    if sigma > MAX_SIGMA {
+        return Err(Error::InvalidParameter(format!("sigma must be at most {MAX_SIGMA}, got {sigma}")));
+    }
+
+    let radius = (3.0 * sigma).ceil() as i64;
+    let weights: Vec<f32> = (-radius..=radius).map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp()).collect();
+    let total: f32 = weights.iter().sum();
+    Ok(weights.iter().map(|w| w / total).collect())
+}
+
+/// Gaussian blur used by the edge detectors before computing gradients.
+pub fn gaussian_blur(image: &GrayImage, sigma: f32, border: BorderMode) -> Result<GrayImage> {
+    let blurred = blur_samples(image.as_raw(), image.width() as usize, image.height() as usize, sigma, border)?;
+
+    let pixels = blurred.iter().map(|v| v.round().clamp(0.0, 255.0) as u8).collect();
+    Ok(ImageBuffer::from_raw(image.width(), image.height(), pixels).expect("blurred buffer has the image size"))
+}
+
+/// Gaussian blur of a floating point response, e.g. to weight a window of gradient products.
+pub fn gaussian_blur_f32(image: &ResponseImage, sigma: f32, border: BorderMode) -> Result<ResponseImage> {
+    let blurred = blur_samples(image.as_raw(), image.width() as usize, image.height() as usize, sigma, border)?;
+    Ok(ImageBuffer::from_raw(image.width(), image.height(), blurred).expect("blurred buffer has the image size"))
+}
+
+/// Separable blur of a row-major `width` x `height` buffer.
+fn blur_samples<T>(samples: &[T], width: usize, height: usize, sigma: f32, border: BorderMode) -> Result<Vec<f32>>
+where
+    T: Copy + Into<f32> + Sync,
+{
+    let kernel = gaussian_kernel(sigma)?;
+    Ok(convolve_separable(samples, width, height, &kernel, &kernel, border))
+}
 
-    let mut kernel = vec![vec![0.0; mask_size]; mask_size];
-    let mut sum = 0.0;
+#[cfg(test)]
+mod tests {
+    use image::Luma;
 
-    // Precompute Gaussian kernel
-    for i in -k..=k {
-        for j in -k..=k {
-            let value = (1.0 / (2.0 * PI * sigma * sigma)) * (-(i * i + j * j) as f32 / (2.0 * sigma * sigma)).exp();
-            kernel[(i + k) as usize][(j + k) as usize] = value;
-            sum += value;
+    use super::*;
+
+    #[test]
+    fn kernel_radius_follows_sigma() {
+        for (sigma, radius) in [(0.3, 1), (1.0, 3), (1.5, 5), (2.2, 7)] {
+            let kernel = gaussian_kernel(sigma).unwrap();
+            assert_eq!(kernel.len(), 2 * radius + 1, "sigma {sigma}");
+            assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+            assert_eq!(kernel.first(), kernel.last());
         }
+        assert!(gaussian_kernel(0.0).is_err());
     }
 
-    // Normalize kernel
-    for i in 0..mask_size {
-        for j in 0..mask_size {
-            kernel[i][j] /= sum;
+    #[test]
+    fn huge_sigmas_are_rejected() {
+        assert_eq!(gaussian_kernel(MAX_SIGMA).unwrap().len(), 601);
+        for sigma in [MAX_SIGMA + 1.0, 20_000.0, 1e30, f32::INFINITY] {
+            assert!(matches!(gaussian_kernel(sigma), Err(Error::InvalidParameter(_))), "sigma {sigma}");
         }
+        let image = GrayImage::new(4, 4);
+        assert!(matches!(gaussian_blur(&image, 1e30, BorderMode::Replicate), Err(Error::InvalidParameter(_))));
     }
 
-    let mut filtered_image = DynamicImage::new_rgba8(width as u32, height as u32);
-
-    for x in 0..width {
-        for y in 0..height {
-            let mut r = 0.0;
-            let mut g = 0.0;
-            let mut b = 0.0;
-            let mut a = 0.0;
-
-            for i in -k..=k {
-                for j in -k..=k {
-                    let nx = x + i;
-                    let ny = y + j;
-                    if nx >= 0 && nx < width && ny >= 0 && ny < height {
-                        let pixel = image.get_pixel(nx as u32, ny as u32);
-                        let weight = kernel[(i + k) as usize][(j + k) as usize];
-                        r += pixel[0] as f32 * weight;
-                        g += pixel[1] as f32 * weight;
-                        b += pixel[2] as f32 * weight;
-                        a += pixel[3] as f32 * weight;
-                    }
-                }
-            }
-
-            filtered_image.put_pixel(x as u32, y as u32, Rgba([
-                r.min(255.0) as u8,
-                g.min(255.0) as u8,
-                b.min(255.0) as u8,
-                a.min(255.0) as u8,
-            ]));
+    #[test]
+    fn flat_images_stay_flat() {
+        let image = GrayImage::from_pixel(7, 5, Luma([90]));
+        for border in [BorderMode::Replicate, BorderMode::Reflect, BorderMode::Reflect101, BorderMode::Wrap] {
+            assert_eq!(gaussian_blur(&image, 2.0, border).unwrap(), image, "{border:?}");
         }
+        // Skipping leaves a black frame as wide as the kernel radius.
+        let skipped = gaussian_blur(&GrayImage::from_pixel(20, 20, Luma([90])), 1.0, BorderMode::Skip).unwrap();
+        assert_eq!(skipped.get_pixel(2, 10).0[0], 0);
+        assert_eq!(skipped.get_pixel(3, 10).0[0], 90);
+        // A black constant border darkens the edges but not the middle of a wide image.
+        let wide = GrayImage::from_pixel(40, 40, Luma([90]));
+        let blurred = gaussian_blur(&wide, 1.0, BorderMode::Constant(0.0)).unwrap();
+        assert!(blurred.get_pixel(0, 0).0[0] < 90);
+        assert_eq!(blurred.get_pixel(20, 20).0[0], 90);
+    }
+
+    #[test]
+    fn impulse_spreads_symmetrically() {
+        let mut image = ResponseImage::new(21, 21);
+        image.put_pixel(10, 10, Luma([1.0]));
+
+        let blurred = gaussian_blur_f32(&image, 1.5, BorderMode::Replicate).unwrap();
+        let value = |x, y| blurred.get_pixel(x, y).0[0];
+        assert!((blurred.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert_eq!(value(8, 10), value(12, 10));
+        assert_eq!(value(10, 8), value(10, 12));
+        assert_eq!(value(8, 10), value(10, 8));
     }
 
-    Ok(filtered_image)
-}
\ No newline at end of file
+    #[test]
+    fn images_smaller_than_the_kernel() {
+        let image = GrayImage::from_fn(2, 1, |x, _| Luma([x as u8 * 100]));
+        for border in [
+            BorderMode::Replicate,
+            BorderMode::Reflect,
+            BorderMode::Reflect101,
+            BorderMode::Wrap,
+            BorderMode::Constant(0.0),
+            BorderMode::Skip,
+        ] {
+            let blurred = gaussian_blur(&image, 3.0, border).unwrap();
+            assert_eq!(blurred.dimensions(), (2, 1), "{border:?}");
+        }
+    }
+}
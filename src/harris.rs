@@ -1,96 +1,137 @@
-use std::io::Cursor;
-use image::{imageops::grayscale, io::Reader as ImageReader, DynamicImage, GenericImage, ImageBuffer, Luma, Rgba};
+use image::{imageops, GrayImage};
+
+use crate::{
+    border::BorderMode,
+    error::{Error, Result},
+    keypoint::{local_maxima, refine_subpixel, Keypoint},
+    parallel,
+    render::{render, Normalization},
+    tensor::StructureTensor,
+    ResponseImage,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HarrisParams {
+    /// Sensitivity factor in `det(M) - k * trace(M)^2`.
+    pub k: f64,
+    /// Standard deviation of the Gaussian window the structure tensor `M` is summed over.
+    pub window_sigma: f32,
+    /// Minimum response of a corner, as a fraction of the strongest response in the image.
+    pub threshold: f32,
+    /// A corner must be the strongest response within this many pixels.
+    pub nms_radius: u32,
+    /// Refine corner positions to sub-pixel accuracy.
+    pub subpixel: bool,
+    /// How the gradients and the window sample outside the image.
+    pub border: BorderMode,
+}
 
-pub fn harris(body: &Vec<u8>) -> Vec<u8> {
+impl Default for HarrisParams {
+    fn default() -> Self {
+        HarrisParams {
+            k: 0.04,
+            window_sigma: 1.5,
+            threshold: 0.01,
+            nms_radius: 3,
+            subpixel: false,
+            border: BorderMode::Replicate,
+        }
+    }
+}
 
-    let img = ImageReader::new(Cursor::new(body)).with_guessed_format().unwrap().decode().unwrap();
-    
-    let gray_scale_img = grayscale(&img);
-    let corner_img = harris_edge_corner_detection(&gray_scale_img);
+/// Harris corner response `R` of every pixel.
+pub fn harris_response(image: &GrayImage, params: &HarrisParams) -> Result<ResponseImage> {
+    let k = params.k;
+    if !k.is_finite() {
+        return Err(Error::InvalidParameter(format!("k must be a number, got {k}")));
+    }
 
-    let mut image_bytes = Vec::new();
-    corner_img.write_to(&mut Cursor::new(&mut image_bytes), image::ImageFormat::Png).unwrap(); 
+    let tensor = StructureTensor::new(image, params.window_sigma, params.border)?;
 
-    return image_bytes;
-}
+    let response = parallel::luma_from_fn(image.width(), image.height(), |x, y| {
+        let (xx, xy, yy) = tensor.get(x, y);
+        let (xx, xy, yy) = (xx as f64, xy as f64, yy as f64);
 
-fn ix(image: &ImageBuffer<Luma<u8>, Vec<u8>>, x: u32, y: u32) -> f64 {
-    let x1 = if x > 0 { x - 1 } else { x };
-    let x2 = if x < image.width() - 1 { x + 1 } else { x };
-    return (image.get_pixel(x2, y).0[0] as f64 - image.get_pixel(x1, y).0[0] as f64) / 2.0;
-}
-fn iy(image: &ImageBuffer<Luma<u8>, Vec<u8>>, x: u32, y: u32) -> f64 {
-    let y1 = if y > 0 { y - 1 } else { y };
-    let y2 = if y < image.height() - 1 { y + 1 } else { y };
-    return (image.get_pixel(x, y2).0[0] as f64 - image.get_pixel(x, y1).0[0] as f64) / 2.0;
-}
+        let det_m = xx * yy - xy * xy;
+        let trace_m = xx + yy;
+        (det_m - k * trace_m * trace_m) as f32
+    });
 
-fn det(m: &Vec<Vec<f64>>) -> f64 {
-    return m[0][0] * m[1][1] - m[1][0] * m[0][1];
+    Ok(response)
 }
 
+/// Largest accepted `nms_radius`; every candidate compares itself with its whole window.
+pub const MAX_NMS_RADIUS: u32 = 100;
 
-fn harris_edge_corner_detection(image: &ImageBuffer<Luma<u8>, Vec<u8>>) -> DynamicImage {
+/// Harris corners: local maxima of the response above `params.threshold`, strongest first.
+pub fn harris_corners(image: &GrayImage, params: &HarrisParams) -> Result<Vec<Keypoint>> {
+    let threshold = params.threshold;
+    if !threshold.is_finite() || threshold < 0.0 {
+        return Err(Error::InvalidParameter(format!("threshold must be a non-negative number, got {threshold}")));
+    }
+    if params.nms_radius > MAX_NMS_RADIUS {
+        let radius = params.nms_radius;
+        return Err(Error::InvalidParameter(format!("nms_radius must be at most {MAX_NMS_RADIUS}, got {radius}")));
+    }
 
-    let width = image.width();
-    let height = image.height();
+    let response = harris_response(image, params)?;
+    let max_r = response.pixels().map(|p| p.0[0]).fold(0.0, f32::max);
 
-    let k = 0.04;
+    let corners = local_maxima(&response, params.nms_radius, threshold * max_r);
 
-    let mut max_r = f64::MIN;
-    let mut min_r = f64::MAX;
-    
-    
-    let mut response = vec![vec![0.0; height as usize]; width as usize];
-    let mut res_image = DynamicImage::new_luma8(image.width(), image.height());
+    if params.subpixel {
+        return Ok(corners.into_iter().map(|corner| refine_subpixel(&response, corner)).collect());
+    }
+    Ok(corners)
+}
 
+/// Harris corner response of a grayscale image, normalised and inverted for display.
+pub fn harris(image: &GrayImage, params: &HarrisParams) -> Result<GrayImage> {
+    let response = harris_response(image, params)?;
 
+    let mut res_image = render(&response, Normalization::MinMax);
 
-    for x in 1..width-1 {
-        for y in 1..height-1 {
-            let i_x = ix(image, x, y);
-            let i_y = iy(image, x, y);
-            let m = vec![
-                vec![i_x * i_x, i_x * i_y],
-                vec![i_x * i_y ,i_y * i_y]
-            ];
+    imageops::invert(&mut res_image);
 
-            let det_m = det(&m);
-            let trace_m = m[0][0] + m[1][1];
+    Ok(res_image)
+}
 
-            if trace_m != 0.0 {
-                let r = det_m - k * trace_m * trace_m;
-                response[x as usize][y as usize] = r;
-                
-                if r > max_r {
-                    max_r = r;
-                }
-                if r < min_r {
-                    min_r = r
-                }
-                
-            }
-            
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use image::Luma;
 
-    for x in 0..width {
-        for y in 0..height {
-            let r = response[x as usize][y as usize];
-            let r_normalized = if max_r != min_r {
-                ((r - min_r) / (max_r - min_r) * 255.0) as u8
-            } else {
-                0
-            };
-            res_image.put_pixel(x, y, Rgba([r_normalized, r_normalized, r_normalized, 255]));
-        }
-    }
+    use super::*;
 
-    res_image.invert();
-    res_image.brighten(10);
+    /// A bright square on a dark background has exactly four corners.
+    #[test]
+    fn finds_the_corners_of_a_square() {
+        let image = GrayImage::from_fn(40, 40, |x, y| {
+            Luma([if (10..30).contains(&x) && (10..30).contains(&y) { 200 } else { 20 }])
+        });
 
-    return res_image;   
-}
+        let corners = harris_corners(&image, &HarrisParams::default()).unwrap();
+        assert_eq!(corners.len(), 4, "{corners:?}");
 
+        for expected in [(10.0, 10.0), (29.0, 10.0), (10.0, 29.0), (29.0, 29.0)] {
+            assert!(
+                corners.iter().any(|c| (c.x - expected.0).abs() <= 1.5 && (c.y - expected.1).abs() <= 1.5),
+                "no corner near {expected:?}: {corners:?}"
+            );
+        }
+        assert!(corners.iter().all(|c| c.score > 0.0));
+    }
 
+    #[test]
+    fn edges_are_not_corners() {
+        let image = GrayImage::from_fn(40, 40, |x, _| Luma([if x < 20 { 20 } else { 200 }]));
+        let response = harris_response(&image, &HarrisParams::default()).unwrap();
+        assert!(response.get_pixel(20, 20).0[0] < 0.0);
+    }
 
+    #[test]
+    fn huge_suppression_radii_are_rejected() {
+        let image = GrayImage::new(8, 8);
+        let params = HarrisParams { nms_radius: MAX_NMS_RADIUS + 1, ..HarrisParams::default() };
+        assert!(matches!(harris_corners(&image, &params), Err(Error::InvalidParameter(_))));
+    }
+}
@@ -0,0 +1,681 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, BufWriter, Read, Write},
+    path::Path,
+};
+
+use serde_json::{json, Value};
+
+/// Longest request line plus headers accepted, so a client cannot grow them without bound.
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+
+/// Longest chunk size line of a chunked body, extensions included.
+const MAX_CHUNK_LINE: u64 = 1024;
+
+/// Bodies at least this large are sent with `Transfer-Encoding: chunked` to HTTP/1.1 clients.
+pub const CHUNKED_THRESHOLD: usize = 256 * 1024;
+
+/// Size of the chunks a chunked response body is split into.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The body buffer grows as data arrives, starting at most this large whatever the
+/// Content-Length claims.
+const BODY_CHUNK: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Other(String),
+}
+
+impl Method {
+    pub fn parse(method: &str) -> Method {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            "PATCH" => Method::Patch,
+            other => Method::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Other(other) => other,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Option<Version> {
+        match version {
+            "HTTP/1.0" => Some(Version::Http10),
+            "HTTP/1.1" => Some(Version::Http11),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Http10 => f.write_str("HTTP/1.0"),
+            Version::Http11 => f.write_str("HTTP/1.1"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub version: Version,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Path parameters filled in by the router, e.g. `algorithm` for `/detect/{algorithm}`.
+    pub params: HashMap<String, String>,
+}
+
+/// Why a request could not be read. Each variant maps to the status code sent back.
+#[derive(Debug)]
+pub enum RequestError {
+    /// The connection closed before any byte of a request arrived, as after a browser's
+    /// preconnect or a TCP health check. There is nobody to answer.
+    Closed,
+    BadRequest(String),
+    /// The connection's read timeout expired before the request was complete.
+    Timeout,
+    PayloadTooLarge(String),
+    HeadersTooLarge,
+    /// A `Transfer-Encoding` other than `chunked`.
+    NotImplemented(String),
+    VersionNotSupported(String),
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> RequestError {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => RequestError::Timeout,
+            _ => RequestError::BadRequest(e.to_string()),
+        }
+    }
+}
+
+impl Request {
+    /// Reads the request line, headers and body from the connection. The body is either
+    /// `Content-Length` bytes or `Transfer-Encoding: chunked`; bodies larger than
+    /// `max_body_size` bytes are rejected, and the body is only allocated as it arrives.
+    pub fn read<R: BufRead>(reader: &mut R, max_body_size: usize) -> Result<Request, RequestError> {
+        let mut head = reader.by_ref().take(MAX_HEAD_SIZE);
+
+        let mut request_line = String::new();
+        if read_head_line(&mut head, &mut request_line)? == 0 {
+            return Err(RequestError::Closed);
+        }
+        let request_line = request_line.trim_end();
+
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(RequestError::BadRequest(format!("Malformed request line: {request_line}")));
+        };
+        let version = Version::parse(version).ok_or_else(|| RequestError::VersionNotSupported(version.to_string()))?;
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        let mut headers = Vec::new();
+        let mut header_line = String::new();
+        loop {
+            header_line.clear();
+            if read_head_line(&mut head, &mut header_line)? == 0 {
+                return Err(RequestError::BadRequest("Connection closed before end of headers".into()));
+            }
+            let line = header_line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| RequestError::BadRequest(format!("Malformed header: {line}")))?;
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut request = Request {
+            method: Method::parse(method),
+            path: percent_decode(path, false),
+            query: parse_query(query),
+            version,
+            headers,
+            body: Vec::new(),
+            params: HashMap::new(),
+        };
+
+        // A proxy in front may pick the other framing, so ambiguous ones are refused and the
+        // connection closed rather than guessed at (RFC 7230, section 3.3.3).
+        let lengths: Vec<&str> = request.headers_named("content-length").into_iter().flat_map(|value| value.split(',')).map(str::trim).collect();
+        if request.headers_named("transfer-encoding").len() > 1 {
+            return Err(RequestError::BadRequest("Repeated Transfer-Encoding header".into()));
+        }
+        if request.header("transfer-encoding").is_some() && !lengths.is_empty() {
+            return Err(RequestError::BadRequest("Both Transfer-Encoding and Content-Length given".into()));
+        }
+        if lengths.windows(2).any(|pair| pair[0] != pair[1]) {
+            return Err(RequestError::BadRequest(format!("Conflicting Content-Length values: {}", lengths.join(", "))));
+        }
+
+        match request.header("transfer-encoding") {
+            Some(encoding) if encoding.eq_ignore_ascii_case("chunked") => {
+                request.body = read_chunked_body(reader, max_body_size)?;
+                return Ok(request);
+            }
+            Some(encoding) => return Err(RequestError::NotImplemented(format!("Transfer-Encoding: {encoding}"))),
+            None => {}
+        }
+
+        let content_length = match lengths.first() {
+            Some(length) => length
+                .parse::<usize>()
+                .map_err(|_| RequestError::BadRequest(format!("Invalid Content-Length: {length}")))?,
+            None => 0,
+        };
+        if content_length > max_body_size {
+            return Err(too_large(content_length, max_body_size));
+        }
+        request.body = Vec::with_capacity(content_length.min(BODY_CHUNK));
+        reader.by_ref().take(content_length as u64).read_to_end(&mut request.body)?;
+        if request.body.len() < content_length {
+            return Err(RequestError::BadRequest("Connection closed before end of body".into()));
+        }
+
+        Ok(request)
+    }
+
+    /// Whether the client wants the connection kept open after this request: the default
+    /// for HTTP/1.1 unless it sends `Connection: close`, opt-in for HTTP/1.0.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").unwrap_or_default();
+        let has = |token: &str| connection.split(',').any(|option| option.trim().eq_ignore_ascii_case(token));
+        match self.version {
+            Version::Http11 => !has("close"),
+            Version::Http10 => has("keep-alive"),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Values of every header called `name`, in the order they were sent.
+    pub fn headers_named(&self, name: &str) -> Vec<&str> {
+        self.headers.iter().filter(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str()).collect()
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+}
+
+/// Built by chaining onto [`Response::new`], e.g.
+/// `Response::new(200).json(&data).with_cache_control("no-store")`.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// An empty response with the standard reason phrase of `status`.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            reason: reason_phrase(status).to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Sets the body and its Content-Type.
+    pub fn body(self, content_type: &str, body: impl Into<Vec<u8>>) -> Response {
+        let mut response = self.with_header("Content-Type", content_type);
+        response.body = body.into();
+        response
+    }
+
+    pub fn html(self, body: impl Into<Vec<u8>>) -> Response {
+        self.body("text/html; charset=utf-8", body)
+    }
+
+    pub fn text(self, body: impl Into<Vec<u8>>) -> Response {
+        self.body("text/plain; charset=utf-8", body)
+    }
+
+    pub fn json(self, body: &Value) -> Response {
+        self.body("application/json", body.to_string())
+    }
+
+    /// An `{"error": message}` JSON body, the shape of every API error.
+    pub fn error(self, message: &str) -> Response {
+        self.json(&json!({ "error": message }))
+    }
+
+    /// A file's contents, typed by the extension of `path` or else by the contents.
+    pub fn file(self, path: &Path, contents: Vec<u8>) -> Response {
+        let content_type = content_type_by_extension(path).unwrap_or_else(|| content_type_by_payload(&contents));
+        self.body(content_type, contents)
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Response {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_cache_control(self, value: &str) -> Response {
+        self.with_header("Cache-Control", value)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Writes the response. `include_body` is false for HEAD requests, which still
+    /// report the Content-Length of the body they would have received. A `chunked`
+    /// body is sent in pieces of [`CHUNK_SIZE`] instead, for HTTP/1.1 clients only.
+    pub fn write_to<W: Write>(&self, writer: W, include_body: bool, chunked: bool) -> io::Result<()> {
+        let chunked = chunked && include_body;
+
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason);
+        for (key, value) in &self.headers {
+            head.push_str(&format!("{key}: {value}\r\n"));
+        }
+        if chunked {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        } else if self.status != 204 && self.status != 304 {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(head.as_bytes())?;
+        if chunked {
+            for chunk in self.body.chunks(CHUNK_SIZE) {
+                write!(writer, "{:x}\r\n", chunk.len())?;
+                writer.write_all(chunk)?;
+                writer.write_all(b"\r\n")?;
+            }
+            writer.write_all(b"0\r\n\r\n")?;
+        } else if include_body {
+            writer.write_all(&self.body)?;
+        }
+        writer.flush()
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+fn content_type_by_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    Some(match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        _ => return None,
+    })
+}
+
+/// Recognises images by their magic bytes and JSON by parsing it.
+fn content_type_by_payload(body: &[u8]) -> &'static str {
+    if let Ok(format) = image::guess_format(body) {
+        return format.to_mime_type();
+    }
+    if serde_json::from_slice::<Value>(body).is_ok() {
+        return "application/json";
+    }
+    "application/octet-stream"
+}
+
+fn too_large(size: usize, max_body_size: usize) -> RequestError {
+    RequestError::PayloadTooLarge(format!("The body of {size} bytes exceeds the limit of {max_body_size} bytes"))
+}
+
+/// Reads a `Transfer-Encoding: chunked` body: hex sized chunks up to a zero sized one,
+/// followed by optional trailers, which are skipped.
+fn read_chunked_body<R: BufRead>(reader: &mut R, max_body_size: usize) -> Result<Vec<u8>, RequestError> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        read_chunk_line(reader, &mut line)?;
+        // Chunk extensions after `;` carry nothing we use.
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| RequestError::BadRequest(format!("Invalid chunk size: {size}")))?;
+        if size == 0 {
+            break;
+        }
+        let total = body.len().saturating_add(size);
+        if total > max_body_size {
+            return Err(too_large(total, max_body_size));
+        }
+
+        reader.by_ref().take(size as u64).read_to_end(&mut body)?;
+        if body.len() < total {
+            return Err(RequestError::BadRequest("Connection closed before end of chunk".into()));
+        }
+        read_chunk_line(reader, &mut line)?;
+        if !line.is_empty() {
+            return Err(RequestError::BadRequest("Chunk longer than its size".into()));
+        }
+    }
+
+    loop {
+        read_chunk_line(reader, &mut line)?;
+        if line.is_empty() {
+            return Ok(body);
+        }
+    }
+}
+
+/// Reads one line of a chunked body into `line`, without the line ending.
+fn read_chunk_line<R: BufRead>(reader: &mut R, line: &mut String) -> Result<(), RequestError> {
+    line.clear();
+    reader.by_ref().take(MAX_CHUNK_LINE).read_line(line)?;
+    if !line.ends_with('\n') {
+        return Err(RequestError::BadRequest("Malformed chunked body".into()));
+    }
+    line.truncate(line.trim_end_matches(['\r', '\n']).len());
+    Ok(())
+}
+
+/// Reads one line of the request head, failing once the head outgrows [`MAX_HEAD_SIZE`].
+fn read_head_line<R: BufRead>(head: &mut io::Take<&mut R>, line: &mut String) -> Result<usize, RequestError> {
+    let read = head.read_line(line)?;
+    if head.limit() == 0 && !line.ends_with('\n') {
+        return Err(RequestError::HeadersTooLarge);
+    }
+    Ok(read)
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key, true), percent_decode(value, true))
+        })
+        .collect()
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// Decodes `%XX` escapes; `+` becomes a space only inside query strings.
+fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex_digit(bytes[i + 1]), hex_digit(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(raw: &[u8]) -> Result<Request, RequestError> {
+        Request::read(&mut &raw[..], 1024)
+    }
+
+    #[test]
+    fn parses_the_request_line_headers_and_body() {
+        let request = read(b"POST /detect/canny?sigma=1.5 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.path, "/detect/canny");
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.query["sigma"], "1.5");
+        assert_eq!(request.header("HOST"), Some("localhost"));
+        assert_eq!(request.body, b"hello");
+
+        // Bare LF line endings are tolerated.
+        let request = read(b"GET / HTTP/1.0\nAccept: */*\n\n").unwrap();
+        assert_eq!((request.method, request.version), (Method::Get, Version::Http10));
+    }
+
+    #[test]
+    fn closed_connections_are_not_malformed_requests() {
+        assert!(matches!(read(b""), Err(RequestError::Closed)));
+        assert!(matches!(read(b"GET"), Err(RequestError::BadRequest(_))));
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        assert!(matches!(read(b"GET /\r\n\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(read(b"GET / HTTP/1.1 extra\r\n\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(read(b"GET / HTTP/2.0\r\n\r\n"), Err(RequestError::VersionNotSupported(_))));
+        assert!(matches!(read(b"GET / HTTP/1.1\r\nNo colon\r\n\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(read(b"GET / HTTP/1.1\r\nHost: a\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(read(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(read(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nshort"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(read(b"POST / HTTP/1.1\r\nContent-Length: 2048\r\n\r\n"), Err(RequestError::PayloadTooLarge(_))));
+    }
+
+    #[test]
+    fn decodes_paths_and_queries() {
+        let request = read(b"GET /a%20b+c?name=J%C3%BCrgen+M&flag&empty=&bad=%zz%+1&%3D=%3d HTTP/1.1\r\n\r\n").unwrap();
+        // `+` only means a space inside the query.
+        assert_eq!(request.path, "/a b+c");
+        assert_eq!(request.query["name"], "Jürgen M");
+        assert_eq!(request.query["flag"], "");
+        assert_eq!(request.query["empty"], "");
+        assert_eq!(request.query["bad"], "%zz% 1");
+        assert_eq!(request.query["="], "=");
+    }
+
+    #[test]
+    fn percent_escapes_need_two_hex_digits() {
+        assert_eq!(percent_decode("%41%4a%4A", false), "AJJ");
+        assert_eq!(percent_decode("%+1", false), "%+1");
+        assert_eq!(percent_decode("%-1%4", false), "%-1%4");
+        assert_eq!(percent_decode("100%", false), "100%");
+    }
+
+    fn chunked(body: &[u8]) -> Result<Request, RequestError> {
+        let mut raw = b"POST /canny HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        raw.extend_from_slice(body);
+        read(&raw)
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let request = chunked(b"5\r\nhello\r\n7;name=value\r\n, world\r\n0\r\n\r\n").unwrap();
+        assert_eq!(request.body, b"hello, world");
+
+        // Trailers are skipped, and the next request starts right after them.
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nA\r\n0123456789\r\n0\r\nX-Checksum: 1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let mut reader = &raw[..];
+        assert_eq!(Request::read(&mut reader, 1024).unwrap().body, b"0123456789");
+        assert_eq!(Request::read(&mut reader, 1024).unwrap().method, Method::Get);
+
+        assert!(matches!(
+            read(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Err(RequestError::NotImplemented(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        // 1000 + 100 bytes exceed the limit of 1024 before the second chunk is read.
+        let mut body = b"3e8\r\n".to_vec();
+        body.extend_from_slice(&[b'a'; 1000]);
+        body.extend_from_slice(b"\r\n64\r\n");
+        assert!(matches!(chunked(&body), Err(RequestError::PayloadTooLarge(_))));
+        assert!(matches!(chunked(b"fffffffffffffffff\r\n"), Err(RequestError::BadRequest(_))));
+
+        assert!(matches!(chunked(b"5\r\nhelloXX0\r\n\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(chunked(b"zz\r\n"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(chunked(b"5\r\nhel"), Err(RequestError::BadRequest(_))));
+        assert!(matches!(chunked(b"5\r\nhello\r\n"), Err(RequestError::BadRequest(_))));
+    }
+
+    #[test]
+    fn ambiguous_framing_is_rejected() {
+        let smuggled = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 4\r\n\r\n0\r\n\r\n";
+        assert!(matches!(read(smuggled), Err(RequestError::BadRequest(_))));
+        let repeated = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n0\r\n\r\n";
+        assert!(matches!(read(repeated), Err(RequestError::BadRequest(_))));
+        let conflicting = b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 5\r\n\r\nhello";
+        assert!(matches!(read(conflicting), Err(RequestError::BadRequest(_))));
+        assert!(matches!(read(b"POST / HTTP/1.1\r\nContent-Length: 2, 5\r\n\r\nhello"), Err(RequestError::BadRequest(_))));
+
+        // Repeating the same length is harmless.
+        let repeated = read(b"POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\nhello").unwrap();
+        assert_eq!(repeated.body, b"hello");
+    }
+
+    /// Writes `response` and reads it back as a request body, chunked or not.
+    fn round_trip(response: &Response, include_body: bool, chunked: bool) -> (String, Vec<u8>) {
+        let mut written = Vec::new();
+        response.write_to(&mut written, include_body, chunked).unwrap();
+        let head_end = written.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8(written[..head_end].to_vec()).unwrap();
+
+        let body = if head.contains("Transfer-Encoding: chunked") {
+            read_chunked_body(&mut &written[head_end..], usize::MAX).unwrap()
+        } else {
+            written[head_end..].to_vec()
+        };
+        (head, body)
+    }
+
+    #[test]
+    fn chunked_responses_round_trip() {
+        let body: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let response = Response::new(200).body("application/octet-stream", body.clone());
+
+        let (head, decoded) = round_trip(&response, true, true);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!head.contains("Content-Length"));
+        assert_eq!(decoded, body);
+
+        let (head, decoded) = round_trip(&response, true, false);
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn head_responses_are_never_chunked() {
+        let response = Response::new(200).text(vec![b'a'; CHUNKED_THRESHOLD]);
+        let (head, body) = round_trip(&response, false, true);
+        assert!(!head.contains("Transfer-Encoding"));
+        assert!(head.contains(&format!("Content-Length: {CHUNKED_THRESHOLD}\r\n")));
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn files_are_typed_by_extension_then_contents() {
+        assert_eq!(content_type_by_extension(Path::new("index.html")), Some("text/html; charset=utf-8"));
+        assert_eq!(content_type_by_extension(Path::new("client/APP.JS")), Some("application/javascript; charset=utf-8"));
+        assert_eq!(content_type_by_extension(Path::new("style.css")), Some("text/css; charset=utf-8"));
+        assert_eq!(content_type_by_extension(Path::new("photo.Jpeg")), Some("image/jpeg"));
+        assert_eq!(content_type_by_extension(Path::new("archive.tar.gz")), None);
+        assert_eq!(content_type_by_extension(Path::new("Makefile")), None);
+
+        let png = Response::new(200).file(Path::new("image"), b"\x89PNG\r\n\x1a\n\0\0\0\0".to_vec());
+        assert_eq!(png.header("Content-Type"), Some("image/png"));
+        let json = Response::new(200).file(Path::new("data"), br#"{"a": 1}"#.to_vec());
+        assert_eq!(json.header("Content-Type"), Some("application/json"));
+        let other = Response::new(200).file(Path::new("blob"), b"hello".to_vec());
+        assert_eq!(other.header("Content-Type"), Some("application/octet-stream"));
+    }
+
+    #[test]
+    fn errors_are_json() {
+        let response = Response::new(408).error("too slow");
+        assert_eq!((response.status, response.reason.as_str()), (408, "Request Timeout"));
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(response.body, br#"{"error":"too slow"}"#);
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use image::{DynamicImage, ImageFormat, ImageReader};
+
+use crate::error::{Error, Result};
+
+/// Pixel count [`decode`] accepts unless changed with [`set_max_pixels`], about 8K x 6K.
+pub const DEFAULT_MAX_PIXELS: u64 = 50_000_000;
+
+static MAX_PIXELS: AtomicU64 = AtomicU64::new(DEFAULT_MAX_PIXELS);
+
+/// Largest image, as width times height, that [`decode`] accepts.
+pub fn set_max_pixels(max_pixels: u64) {
+    MAX_PIXELS.store(max_pixels, Ordering::Relaxed);
+}
+
+/// Decodes an uploaded image, guessing the format from its contents. Images with more
+/// pixels than allowed by [`set_max_pixels`] are rejected before they are decoded.
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    decode_within(bytes, MAX_PIXELS.load(Ordering::Relaxed))
+}
+
+fn decode_within(bytes: &[u8], max_pixels: u64) -> Result<DynamicImage> {
+    let reader = || ImageReader::new(Cursor::new(bytes)).with_guessed_format();
+    if reader()?.format().is_none() {
+        return Err(Error::UnsupportedFormat("Could not detect the image format".into()));
+    }
+
+    // Only the header is read here, so a small file claiming huge dimensions costs nothing.
+    let (width, height) = reader()?.into_dimensions()?;
+    if u64::from(width) * u64::from(height) > max_pixels {
+        return Err(Error::TooLarge(format!("The image of {width}x{height} pixels exceeds the limit of {max_pixels} pixels")));
+    }
+
+    let image = reader()?.decode()?;
+    if image.width() == 0 || image.height() == 0 {
+        return Err(Error::Decode("Image has no pixels".into()));
+    }
+    Ok(image)
+}
+
+pub fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut image_bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut image_bytes), ImageFormat::Png)?;
+    Ok(image_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    #[test]
+    fn rejects_images_above_the_pixel_limit() {
+        let png = encode_png(&DynamicImage::ImageLuma8(GrayImage::from_pixel(5, 4, Luma([128])))).unwrap();
+
+        assert_eq!(decode_within(&png, 20).unwrap().width(), 5);
+        assert!(matches!(decode_within(&png, 19), Err(Error::TooLarge(_))));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    convolution::Filter,
+    error::{Error, Result},
+};
+
+/// Largest accepted custom operator, in rows and columns.
+pub const MAX_KERNEL_SIZE: usize = 31;
+
+/// Largest magnitude of a custom operator coefficient.
+pub const MAX_COEFFICIENT: i32 = 1 << 16;
+
+/// A pair of square x and y derivative operators, indexed `[row][column]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operators {
+    x: Vec<Vec<i32>>,
+    y: Vec<Vec<i32>>,
+    /// Angle in degrees between the operators' x axis and the image x axis.
+    rotation: f32,
+}
+
+impl Operators {
+    /// Custom operators; both must be square, of the same size and respond to a step edge.
+    /// Every pixel costs `size²` multiply-adds, so the size and coefficients are bounded.
+    pub fn new(x: Vec<Vec<i32>>, y: Vec<Vec<i32>>) -> Result<Operators> {
+        let size = x.len();
+        if size < 2 || [&x, &y].iter().any(|op| op.len() != size || op.iter().any(|row| row.len() != size)) {
+            return Err(Error::InvalidParameter("Operators must be square, of the same size and at least 2x2".into()));
+        }
+        if size > MAX_KERNEL_SIZE {
+            return Err(Error::InvalidParameter(format!(
+                "Operators must be at most {MAX_KERNEL_SIZE}x{MAX_KERNEL_SIZE}, got {size}x{size}"
+            )));
+        }
+        if [&x, &y].iter().any(|op| op.iter().flatten().any(|c| c.unsigned_abs() > MAX_COEFFICIENT as u32)) {
+            return Err(Error::InvalidParameter(format!("Operator coefficients must be within ±{MAX_COEFFICIENT}")));
+        }
+        let operators = Operators { x, y, rotation: 0.0 };
+        if operators.gain() == 0 {
+            return Err(Error::InvalidParameter("Operators need at least one positive coefficient".into()));
+        }
+        Ok(operators)
+    }
+
+    /// Separable operator: `smooth` across the derivative direction, `derivative` along it.
+    fn separable(smooth: &[i32], derivative: &[i32]) -> Operators {
+        let x: Vec<Vec<i32>> = smooth.iter().map(|s| derivative.iter().map(|d| s * d).collect()).collect();
+        let y = (0..x.len()).map(|row| x.iter().map(|column| column[row]).collect()).collect();
+        Operators { x, y, rotation: 0.0 }
+    }
+
+    pub fn x(&self) -> &[Vec<i32>] {
+        &self.x
+    }
+
+    pub fn y(&self) -> &[Vec<i32>] {
+        &self.y
+    }
+
+    pub fn size(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    /// Sum of the positive x coefficients, i.e. the response to a unit step edge.
+    /// Saturates instead of overflowing, although [`Operators::new`]'s bounds keep it far below.
+    pub fn gain(&self) -> i32 {
+        self.x.iter().flatten().filter(|c| **c > 0).fold(0, |sum, c| sum.saturating_add(*c))
+    }
+
+    /// The x and y operators as convolution filters.
+    pub fn filters(&self) -> (Filter, Filter) {
+        let filter = |op: &[Vec<i32>]| {
+            Filter::new(&op.iter().map(|row| row.iter().map(|&c| c as f32).collect()).collect::<Vec<_>>())
+        };
+        (filter(&self.x), filter(&self.y))
+    }
+}
+
+/// Gradient operators selectable for the Sobel and Canny pipelines.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Kernel {
+    #[default]
+    Sobel3,
+    Sobel5,
+    Sobel7,
+    Scharr,
+    Prewitt,
+    /// 2x2 diagonal differences; the gradient is anchored at the top left pixel.
+    RobertsCross,
+    Custom(Operators),
+}
+
+impl Kernel {
+    /// Kernel by name; custom kernels have to be built with [`Operators::new`].
+    pub fn parse(name: &str) -> Option<Kernel> {
+        match name {
+            "sobel" | "sobel3" => Some(Kernel::Sobel3),
+            "sobel5" => Some(Kernel::Sobel5),
+            "sobel7" => Some(Kernel::Sobel7),
+            "scharr" => Some(Kernel::Scharr),
+            "prewitt" => Some(Kernel::Prewitt),
+            "roberts" => Some(Kernel::RobertsCross),
+            _ => None,
+        }
+    }
+
+    pub fn operators(&self) -> Operators {
+        match self {
+            Kernel::Sobel3 => Operators::separable(&[1, 2, 1], &[-1, 0, 1]),
+            Kernel::Sobel5 => Operators::separable(&[1, 4, 6, 4, 1], &[-1, -2, 0, 2, 1]),
+            Kernel::Sobel7 => Operators::separable(&[1, 6, 15, 20, 15, 6, 1], &[-1, -4, -5, 0, 5, 4, 1]),
+            Kernel::Scharr => Operators::separable(&[3, 10, 3], &[-1, 0, 1]),
+            Kernel::Prewitt => Operators::separable(&[1, 1, 1], &[-1, 0, 1]),
+            // Differences along the diagonals, so the measured gradient is turned by 45 degrees.
+            Kernel::RobertsCross => Operators {
+                x: vec![vec![-1, 0], vec![0, 1]],
+                y: vec![vec![0, -1], vec![1, 0]],
+                rotation: 45.0,
+            },
+            Kernel::Custom(operators) => operators.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(size: usize, c: i32) -> Vec<Vec<i32>> {
+        (0..size).map(|_| (0..size).map(|column| if column == 0 { -c } else { c }).collect()).collect()
+    }
+
+    #[test]
+    fn custom_operators_are_bounded() {
+        assert_eq!(Operators::new(square(3, 1), square(3, 1)).unwrap().gain(), 6);
+        assert!(Operators::new(square(MAX_KERNEL_SIZE, MAX_COEFFICIENT), square(MAX_KERNEL_SIZE, 1)).is_ok());
+
+        let too_large = square(MAX_KERNEL_SIZE + 1, 1);
+        assert!(matches!(Operators::new(too_large.clone(), too_large), Err(Error::InvalidParameter(_))));
+
+        // Used to overflow the gain.
+        let huge = vec![vec![i32::MAX, i32::MAX], vec![0, 0]];
+        assert!(matches!(Operators::new(huge, square(2, 1)), Err(Error::InvalidParameter(_))));
+        let negative = vec![vec![i32::MIN, 1], vec![0, 1]];
+        assert!(matches!(Operators::new(square(2, 1), negative), Err(Error::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn gain_saturates() {
+        let operators = Operators { x: vec![vec![i32::MAX, i32::MAX], vec![-1, 0]], y: square(2, 1), rotation: 0.0 };
+        assert_eq!(operators.gain(), i32::MAX);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{parallel, ResponseImage};
+
+/// A detected corner at pixel coordinates, with the detector's response as score.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keypoint {
+    pub x: f32,
+    pub y: f32,
+    pub score: f32,
+}
+
+/// Pixels whose response exceeds `min_score` and is the largest within `radius` pixels,
+/// strongest first. On plateaus only the first pixel in raster order survives.
+pub fn local_maxima(response: &ResponseImage, radius: u32, min_score: f32) -> Vec<Keypoint> {
+    let width = response.width();
+    let height = response.height();
+
+    let maxima = parallel::luma_from_fn(width, height, |x, y| {
+        let score = response.get_pixel(x, y).0[0];
+        // Also skips NaN scores.
+        if score.is_nan() || score <= min_score {
+            return 0;
+        }
+
+        let is_maximum = (y.saturating_sub(radius)..=(y + radius).min(height - 1)).all(|ny| {
+            (x.saturating_sub(radius)..=(x + radius).min(width - 1)).all(|nx| {
+                let other = response.get_pixel(nx, ny).0[0];
+                // Earlier neighbours must be strictly smaller, later ones may tie.
+                if (ny, nx) < (y, x) { other < score } else { other <= score }
+            })
+        });
+        u8::from(is_maximum)
+    });
+
+    // Collected in raster order, so the stable sort breaks ties the same way every time.
+    let mut keypoints: Vec<Keypoint> = maxima
+        .enumerate_pixels()
+        .filter(|(_, _, is_maximum)| is_maximum.0[0] != 0)
+        .map(|(x, y, _)| Keypoint { x: x as f32, y: y as f32, score: response.get_pixel(x, y).0[0] })
+        .collect();
+
+    keypoints.sort_by(|a, b| b.score.total_cmp(&a.score));
+    keypoints
+}
+
+/// Moves a keypoint to the peak of a parabola fitted through its response and its
+/// two neighbours, separately in x and y. Offsets stay within half a pixel.
+pub fn refine_subpixel(response: &ResponseImage, keypoint: Keypoint) -> Keypoint {
+    let (x, y) = (keypoint.x.round() as u32, keypoint.y.round() as u32);
+    let value = |x: u32, y: u32| response.get_pixel(x, y).0[0];
+    let center = value(x, y);
+
+    let offset = |before: f32, after: f32| {
+        let curvature = before - 2.0 * center + after;
+        if curvature < 0.0 {
+            (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        }
+    };
+
+    let dx = if x > 0 && x + 1 < response.width() { offset(value(x - 1, y), value(x + 1, y)) } else { 0.0 };
+    let dy = if y > 0 && y + 1 < response.height() { offset(value(x, y - 1), value(x, y + 1)) } else { 0.0 };
+
+    Keypoint { x: x as f32 + dx, y: y as f32 + dy, ..keypoint }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    #[test]
+    fn subpixel_refinement_finds_parabola_peak() {
+        // Peak of a paraboloid at (10.3, 7.8), sampled on the pixel grid.
+        let response = ResponseImage::from_fn(20, 16, |x, y| {
+            Luma([100.0 - (x as f32 - 10.3).powi(2) - (y as f32 - 7.8).powi(2)])
+        });
+
+        let keypoints = local_maxima(&response, 1, 0.0);
+        assert_eq!(keypoints.len(), 1);
+        assert_eq!((keypoints[0].x, keypoints[0].y), (10.0, 8.0));
+
+        let refined = refine_subpixel(&response, keypoints[0]);
+        assert!((refined.x - 10.3).abs() < 1e-3 && (refined.y - 7.8).abs() < 1e-3, "{refined:?}");
+        assert_eq!(refined.score, keypoints[0].score);
+    }
+}
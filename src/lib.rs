@@ -0,0 +1,50 @@
+/*
+################################################################################
+# Reference: https://en.wikipedia.org/wiki/Feature_(computer_vision)#Detectors #
+################################################################################
+*/
+
+//! Edge and corner detectors working directly on `image` buffers.
+//!
+//! ```no_run
+//! use rust_server_playground::{canny, CannyParams};
+//!
+//! let gray = image::open("test.png").unwrap().into_luma8();
+//! let edges = canny(&gray, &CannyParams { sigma: 1.4, low_threshold: 0.1, high_threshold: 0.2, ..Default::default() }).unwrap();
+//! edges.save("edges.png").unwrap();
+//! ```
+
+pub mod border;
+pub mod canny;
+pub mod convolution;
+pub mod error;
+pub mod gausian_blur;
+pub mod harris;
+pub mod image_io;
+pub mod kernel;
+pub mod keypoint;
+pub mod overlay;
+pub mod parallel;
+pub mod render;
+pub mod shi;
+pub mod sobel;
+pub mod tensor;
+
+use image::{ImageBuffer, Luma};
+
+/// Per-pixel floating point detector response, e.g. the Harris `R` score or a gradient
+/// magnitude. Use [`render`] to turn it into an 8-bit image.
+pub type ResponseImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+pub use canny::{canny, canny_blurred, canny_from_gradients, CannyParams, Suppression};
+pub use convolution::{convolve, convolve_separable, Filter};
+pub use error::{Error, Result};
+pub use border::BorderMode;
+pub use gausian_blur::{gaussian_blur, gaussian_blur_f32, gaussian_kernel};
+pub use harris::{harris, harris_corners, harris_response, HarrisParams};
+pub use kernel::{Kernel, Operators};
+pub use keypoint::Keypoint;
+pub use overlay::{draw_edges, draw_keypoints, EdgeColoring, Marker};
+pub use render::{render, Normalization};
+pub use shi::{shi, shi_corners, shi_response, ShiParams};
+pub use sobel::{sobel, sobel_blurred, sobel_operator, sobel_response, SobelParams};
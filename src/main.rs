@@ -1,318 +1,1054 @@
-/*
-################################################################################
-# Reference: https://en.wikipedia.org/wiki/Feature_(computer_vision)#Detectors #
-################################################################################
-*/
-
-mod canny;
-mod gausian_blur;
-mod harris;
-mod sobel;
-mod shi;
+#[macro_use]
+mod logger;
+mod cli;
+mod config;
+mod cors;
+mod http;
+mod multipart;
+mod pool;
+mod router;
+mod session;
 
 use std::{
-    env, fs, io::{BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}, str, sync::{mpsc, Arc, Mutex}, thread, time::Instant
+    collections::HashMap, env, fs, iter, process, io::{self, BufRead, BufReader}, net::{TcpListener, TcpStream}, path::Path, str, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, OnceLock}, thread, time::{Duration, Instant}
 };
 use base64::{engine::general_purpose::STANDARD, Engine};
-use canny::canny;
-use shi::shi;
+use image::{imageops::grayscale, DynamicImage, GrayImage, RgbImage};
+use cli::Command;
+use config::Config;
+use http::{Method, Request, RequestError, Response, Version, CHUNKED_THRESHOLD};
+use multipart::Multipart;
+use pool::{Queued, ThreadPool};
+use router::Router;
+use rust_server_playground::{
+    canny_from_gradients, draw_edges, draw_keypoints, gaussian_blur, harris, harris_corners, image_io, parallel, render, shi,
+    shi_corners, sobel_operator, BorderMode, CannyParams, EdgeColoring, Error, HarrisParams, Kernel, Keypoint, Marker,
+    Normalization, Operators, ResponseImage, Result, ShiParams, Suppression,
+};
+use session::Sessions;
 use serde_json::{json, Value};
 
-use crate::{harris::harris, sobel::sobel};
-
 
+/// Detector parameters for a single request.
 #[derive(Clone, Debug)]
-struct Results {
-    canny: String,
-    sobel: String,
-    harris: String,
-    shi: String,
+struct Params {
+    sigma: f32,
+    threshold: f32,
+    /// Canny hysteresis thresholds; `high` defaults to `threshold` and `low` to half of `high`.
+    low: Option<f32>,
+    high: Option<f32>,
+    suppression: Suppression,
+    /// How gradient magnitudes are rendered to PNG.
+    normalization: Normalization,
+    /// Gradient operators used by Sobel and Canny.
+    kernel: Kernel,
+    /// Harris sensitivity factor.
+    k: f64,
+    /// Harris corner threshold, set along with `threshold`; Harris' own default when unset.
+    harris_threshold: Option<f32>,
+    /// Harris non-maximum suppression radius in pixels.
+    nms_radius: u32,
+    /// Structure tensor window of Harris and Shi-Tomasi; each uses its own default when unset.
+    window_sigma: Option<f32>,
+    /// Shi-Tomasi corner selection.
+    quality_level: f32,
+    min_distance: f32,
+    max_corners: usize,
+    /// Whether Harris and Shi-Tomasi return the image, the keypoints or both.
+    output: Output,
+    /// Refine keypoints to sub-pixel accuracy.
+    subpixel: bool,
+    /// Draw the detections over the original image.
+    overlay: bool,
+    edge_coloring: EdgeColoring,
+    marker: Marker,
+    /// How every detector samples outside the image.
+    border: BorderMode,
 }
-#[derive(Clone, Debug)]
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            sigma: 1.0,
+            threshold: 0.3,
+            low: None,
+            high: None,
+            suppression: Suppression::Quantized,
+            normalization: Normalization::Clamp,
+            kernel: Kernel::Sobel3,
+            k: 0.04,
+            harris_threshold: None,
+            nms_radius: 3,
+            window_sigma: None,
+            quality_level: 0.01,
+            min_distance: 10.0,
+            max_corners: 0,
+            output: Output::Image,
+            subpixel: false,
+            overlay: false,
+            edge_coloring: EdgeColoring::Strength,
+            marker: Marker::Circle,
+            border: BorderMode::Replicate,
+        }
+    }
+}
+
+/// Everything a connection handler needs, shared by the workers.
+struct Server {
+    router: Router<Arc<Mutex<ComputerVison>>>,
+    cv: Arc<Mutex<ComputerVison>>,
+    config: Config,
+    /// Connections waiting for a worker; idle kept-alive connections make way for them.
+    queued: Queued,
+    shutdown: Arc<AtomicBool>,
+}
+
+/// How often an idle connection checks for waiting connections and shutdown.
+const IDLE_POLL: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
 struct ComputerVison {
+    /// Parameters used when neither the request nor the session sets them.
+    defaults: Params,
+    sessions: Sessions,
+}
+
+impl ComputerVison {
+    /// The session's defaults, falling back to the server defaults.
+    fn params(&self, session: Option<&str>) -> Params {
+        let mut params = self.defaults.clone();
+        if let Some(session) = session.and_then(|session| self.sessions.get(session)) {
+            params.sigma = session.sigma.unwrap_or(params.sigma);
+            if let Some(threshold) = session.threshold {
+                params.threshold = threshold;
+                params.harris_threshold = Some(threshold);
+            }
+        }
+        params
+    }
+}
+
+/// Image and parameter fields carried by a detector request body.
+struct Payload {
     image: Vec<u8>,
+    fields: HashMap<String, String>,
+}
+
+/// An upload decoded once and shared read-only by every detector of a request.
+struct Decoded {
+    /// The upload as decoded, in its own colour type.
+    image: DynamicImage,
+    /// The original colour image, for overlays.
+    color: OnceLock<RgbImage>,
+    gray: GrayImage,
     sigma: f32,
-    threshold: f32,
-    results: Results
+    border: BorderMode,
+    blurred: OnceLock<GrayImage>,
 }
 
-impl ComputerVison {
-    fn canny(&mut self) -> String {
-        let res = canny(&self.image, &self.sigma, &self.threshold);
-        let encode = STANDARD.encode(&res); 
-        self.results.canny = encode.clone();
-        return encode;
+impl Decoded {
+    fn new(image: &[u8], sigma: f32, border: BorderMode) -> Result<Decoded> {
+        let image = image_io::decode(image)?;
+        Ok(Decoded {
+            gray: grayscale(&image),
+            image,
+            color: OnceLock::new(),
+            sigma,
+            border,
+            blurred: OnceLock::new(),
+        })
+    }
+
+    /// The original as RGB, converted on first use since only overlays need it.
+    fn color(&self) -> &RgbImage {
+        self.color.get_or_init(|| self.image.to_rgb8())
+    }
+
+    /// The Gaussian blurred grayscale image, computed on first use.
+    fn blurred(&self) -> Result<&GrayImage> {
+        if let Some(blurred) = self.blurred.get() {
+            return Ok(blurred);
+        }
+        let blurred = gaussian_blur(&self.gray, self.sigma, self.border)?;
+        Ok(self.blurred.get_or_init(|| blurred))
     }
-    fn sobel(&mut self) -> String {
-        let res = sobel(&self.image, &self.sigma);
-        let encode = STANDARD.encode(&res); 
-        self.results.sobel = encode.clone();
-        return encode;
+}
+
+/// What a detection request returns: the rendered image, the corner keypoints or both.
+/// Edge detectors have no keypoints and always return their image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Output {
+    Image,
+    Keypoints,
+    Both,
+}
+
+impl Output {
+    fn parse(name: &str) -> Option<Output> {
+        match name {
+            "image" => Some(Output::Image),
+            "keypoints" => Some(Output::Keypoints),
+            "both" => Some(Output::Both),
+            _ => None,
+        }
     }
-    fn harris(&mut self) -> String {
-        let res = harris(&self.image);
-        let encode = STANDARD.encode(&res); 
-        self.results.harris = encode.clone();
-        return encode;
+
+    fn image(self) -> bool {
+        self != Output::Keypoints
     }
-    fn shi(&mut self) -> String {
-        let res = shi(&self.image, &self.threshold);
-        let encode = STANDARD.encode(&res); 
-        self.results.shi = encode.clone();
-        return encode;    
+
+    fn keypoints(self) -> bool {
+        self != Output::Image
     }
 }
 
-fn main() {
+/// Result of one detector.
+struct Detection {
+    image: Option<DynamicImage>,
+    keypoints: Option<Vec<Keypoint>>,
+}
 
-    let args: Vec<String> = env::args().collect();
-    let mut port = String::from("8080");
+impl Detection {
+    fn image(image: impl Into<DynamicImage>) -> Detection {
+        Detection { image: Some(image.into()), keypoints: None }
+    }
 
-    for i in 0..args.len() {
-        if args[i] == "port" && i + 1 < args.len() {
-            port = args[i+1].clone();
-            break;
+    fn encode(self) -> Result<Encoded> {
+        let base64 = match self.image {
+            Some(image) => Some(STANDARD.encode(image_io::encode_png(&image)?)),
+            None => None,
+        };
+        Ok(Encoded { base64, keypoints: self.keypoints })
+    }
+}
+
+/// A detection ready to be sent: the image as base64 PNG.
+struct Encoded {
+    base64: Option<String>,
+    keypoints: Option<Vec<Keypoint>>,
+}
+
+/// Detectors run without holding the shared state lock so requests can be served concurrently.
+type Detector = fn(&Decoded, &Params) -> Result<Detection>;
+
+/// Detectors served as `POST /<name>` and `POST /detect/<name>`.
+const DETECTORS: &[(&str, Detector)] = &[
+    ("canny", |decoded, params| {
+        let high_threshold = params.high.unwrap_or(params.threshold);
+        let low_threshold = params.low.unwrap_or(high_threshold * 0.5);
+        let canny_params = CannyParams {
+            sigma: params.sigma,
+            low_threshold,
+            high_threshold,
+            suppression: params.suppression,
+            kernel: params.kernel.clone(),
+            border: params.border,
+        };
+        let (magnitude, orientation) = gradients(decoded, params)?;
+        let edges = canny_from_gradients(&magnitude, &orientation, &canny_params)?;
+        if params.overlay {
+            return Ok(Detection::image(edge_overlay(decoded, &edges, &magnitude, &orientation, params)));
+        }
+        Ok(Detection::image(edges))
+    }),
+    ("sobel", |decoded, params| {
+        let (magnitude, orientation) = gradients(decoded, params)?;
+        let edges = render(&magnitude, params.normalization);
+        if params.overlay {
+            return Ok(Detection::image(edge_overlay(decoded, &edges, &magnitude, &orientation, params)));
+        }
+        Ok(Detection::image(edges))
+    }),
+    ("harris", |decoded, params| {
+        let defaults = HarrisParams::default();
+        let window_sigma = params.window_sigma.unwrap_or(defaults.window_sigma);
+        let harris_params = HarrisParams {
+            k: params.k,
+            window_sigma,
+            threshold: params.harris_threshold.unwrap_or(defaults.threshold),
+            nms_radius: params.nms_radius,
+            subpixel: params.subpixel,
+            border: params.border,
+        };
+        corner_detection(
+            decoded,
+            params,
+            || harris(&decoded.gray, &harris_params),
+            || harris_corners(&decoded.gray, &harris_params),
+        )
+    }),
+    ("shi", |decoded, params| {
+        let shi_params = ShiParams {
+            quality_level: params.quality_level,
+            min_distance: params.min_distance,
+            max_corners: params.max_corners,
+            window_sigma: params.window_sigma.unwrap_or(ShiParams::default().window_sigma),
+            subpixel: params.subpixel,
+            border: params.border,
+        };
+        corner_detection(
+            decoded,
+            params,
+            || shi(&decoded.gray, &shi_params),
+            || shi_corners(&decoded.gray, &shi_params),
+        )
+    }),
+];
+
+/// Gradient magnitude and orientation of the blurred image, shared by the edge detectors
+/// and their overlays.
+fn gradients(decoded: &Decoded, params: &Params) -> Result<(ResponseImage, ResponseImage)> {
+    Ok(sobel_operator(decoded.blurred()?, &params.kernel.operators(), params.border))
+}
+
+/// Edges blended over the original image; `edges` is the opacity of every pixel.
+fn edge_overlay(
+    decoded: &Decoded,
+    edges: &GrayImage,
+    magnitude: &ResponseImage,
+    orientation: &ResponseImage,
+    params: &Params,
+) -> DynamicImage {
+    let mut image = decoded.color().clone();
+    draw_edges(&mut image, edges, magnitude, orientation, params.edge_coloring);
+    image.into()
+}
+
+/// Runs a corner detector for the requested output; with `overlay` the image shows the
+/// corners drawn over the original instead of the detector's own rendering.
+fn corner_detection(
+    decoded: &Decoded,
+    params: &Params,
+    image: impl FnOnce() -> Result<GrayImage>,
+    corners: impl FnOnce() -> Result<Vec<Keypoint>>,
+) -> Result<Detection> {
+    let keypoints = (params.output.keypoints() || params.overlay).then(corners).transpose()?;
+
+    let image = match (params.output.image(), &keypoints) {
+        (false, _) => None,
+        (true, Some(keypoints)) if params.overlay => {
+            let mut image = decoded.color().clone();
+            draw_keypoints(&mut image, keypoints, params.marker);
+            Some(image.into())
+        }
+        (true, _) => Some(image()?.into()),
+    };
+
+    Ok(Detection { image, keypoints: keypoints.filter(|_| params.output.keypoints()) })
+}
+
+fn main() {
+    match cli::parse(env::args().skip(1)) {
+        Ok(Command::Serve(config)) => serve(config),
+        Ok(Command::Detect(options)) => process::exit(cli::detect(options)),
+        Ok(Command::Help) => println!("{}", cli::USAGE),
+        Err(message) => {
+            eprintln!("{message}\n\n{}", cli::USAGE);
+            process::exit(2);
         }
     }
+}
+
+/// Runs the HTTP server until Ctrl+C / SIGTERM.
+fn serve(config: Config) {
+    logger::set_level(config.log_level);
+    image_io::set_max_pixels(config.max_pixels);
+    if let Some(threads) = config.threads {
+        parallel::set_threads(threads);
+    }
 
     let cv = Arc::new(Mutex::new(ComputerVison {
-        image: vec![0],
-        sigma: 1.0,
-        threshold: 0.3,
-        results: Results {
-            canny: String::new(),
-            sobel: String::new(),
-            harris: String::new(),
-            shi: String::new()
-        }
+        defaults: config.defaults.clone(),
+        sessions: Sessions::default(),
     }));
 
+    let shutdown = Arc::new(AtomicBool::new(false));
+    {
+        let shutdown = Arc::clone(&shutdown);
+        ctrlc::set_handler(move || shutdown.store(true, Ordering::SeqCst)).expect("Failed to install signal handler");
+    }
+
+    let listener = match TcpListener::bind((config.host.as_str(), config.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind {}:{}: {e}", config.host, config.port);
+            process::exit(1);
+        }
+    };
+    // Non-blocking accept so the loop can notice a shutdown request.
+    listener.set_nonblocking(true).expect("Failed to configure the listener");
+    let threads = match config.threads {
+        Some(threads) => threads.to_string(),
+        None => format!("up to {}", parallel::threads()),
+    };
+    info!(
+        "Server running on {}:{} with {} workers, {threads} threads per request",
+        config.host,
+        config.port,
+        config.workers
+    );
+
+    let pool = ThreadPool::new(config.workers, config.queue);
+    let server = Arc::new(Server {
+        router: routes(&config.static_dir),
+        cv,
+        queued: pool.queued(),
+        shutdown: Arc::clone(&shutdown),
+        config,
+    });
+
+    while !shutdown.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(20));
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = stream.set_nonblocking(false) {
+            warn!("Failed to configure connection: {e}");
+            continue;
+        }
+
+        // Keep a handle so the client can still be told we are busy.
+        let overflow = stream.try_clone();
+        let server = Arc::clone(&server);
+        if pool.execute(move || handle_connection(stream, &server)).is_err() {
+            if let Ok(stream) = overflow {
+                let response = Response::new(503).error("The server is busy, try again")
+                    .with_header("Retry-After", "1")
+                    .with_header("Connection", "close");
+                let _ = response.write_to(&stream, true, false);
+            }
+        }
+    }
+
+    info!("Shutting down, waiting for in-flight requests");
+    drop(pool);
+    info!("Server stopped");
+}
+
+fn routes(static_dir: &Path) -> Router<Arc<Mutex<ComputerVison>>> {
+    let mut router = Router::new(|request, _| response_404(request));
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).unwrap();
-    println!("Server running on 127.0.0.1:{}", port);
+    for (route, file) in [("/", "index.html"), ("/app.js", "app.js"), ("/style.css", "style.css")] {
+        let path = static_dir.join(file);
+        router.get(route, move |_, _| static_file(&path));
+    }
+    router
+        .post("/setSigma", set_sigma)
+        .post("/setThreshold", set_threshold)
+        .post("/all", run_all)
+        .post("/detect/{algorithm}", detect);
 
-    for stream in listener.incoming() {
-        print!("Hello");
-        let stream = stream.unwrap();
-        handle_connection(stream, cv.clone());
+    for (name, detector) in DETECTORS {
+        router.post(&format!("/{name}"), move |request, cv| {
+            debug!("Start Processing {name}");
+            run_detector(request, cv, *detector)
+        });
     }
+
+    router
 }
 
-fn handle_connection(mut stream: TcpStream, cv: Arc<Mutex<ComputerVison>>) {
+/// Serves requests on one connection until the client or the server ends it.
+fn handle_connection(stream: TcpStream, server: &Server) {
+    let config = &server.config;
+    let mut reader = BufReader::new(&stream);
 
-    
-    let mut buf_reader = BufReader::new(&mut stream);
-    let mut request_line = "".to_string();
-    buf_reader.read_line(&mut request_line).unwrap();
-    request_line = request_line.replace("\r\n", "");
-    
-    let mut header_line = String::new();
-    let mut headers = Vec::new();
-    
-    loop {
-        header_line.clear();
-        buf_reader.read_line(&mut header_line).unwrap();
-        if header_line == "\r\n" {
+    for first in iter::once(true).chain(iter::repeat(false)) {
+        if !first && !await_request(&mut reader, server) {
             break;
         }
-        headers.push(header_line.clone());
-    }
-    
-    let content_length = headers
-    .iter()
-    .filter_map(|header| {
-        if header.to_lowercase().starts_with("content-length") {
-            header
-            .split_whitespace()
-            .nth(1)
-            .and_then(|v| v.trim().parse::<usize>().ok())
+        // A client that stops sending mid-request must not hold a worker forever.
+        if let Err(e) = stream.set_read_timeout(Some(config.read_timeout)) {
+            warn!("Failed to configure connection: {e}");
+            return;
+        }
+
+        let (response, request) = match Request::read(&mut reader, config.max_body_size) {
+            Ok(mut request) => {
+                info!("Request: {} {} {}", request.method, request.path, request.version);
+                let response = match config.cors.preflight(&request) {
+                    Some(response) => response,
+                    None => server.router.handle(&mut request, &server.cv),
+                };
+                (config.cors.apply(&request, response), Some(request))
+            }
+            Err(RequestError::Closed) => {
+                debug!("Connection closed without a request");
+                break;
+            }
+            Err(e) => (response_request_error(e), None),
+        };
+
+        // Everything but the static files is computed per request.
+        let response = match response.header("Cache-Control") {
+            Some(_) => response,
+            None => response.with_cache_control("no-store"),
+        };
+
+        // After a request that could not be read, the rest of the stream cannot be trusted.
+        let keep_alive = request.as_ref().is_some_and(Request::keep_alive)
+            && !config.idle_timeout.is_zero()
+            && !server.shutdown.load(Ordering::SeqCst);
+        let response = if keep_alive {
+            response
+                .with_header("Connection", "keep-alive")
+                .with_header("Keep-Alive", &format!("timeout={}", config.idle_timeout.as_secs()))
         } else {
-            None
+            response.with_header("Connection", "close")
+        };
+
+        let include_body = request.as_ref().is_none_or(|request| request.method != Method::Head);
+        let chunked = request.as_ref().is_some_and(|request| request.version == Version::Http11)
+            && response.body.len() >= CHUNKED_THRESHOLD;
+        if let Err(e) = response.write_to(&stream, include_body, chunked) {
+            warn!("Failed to send response: {e}");
+            break;
         }
-        
-        })
-        .next()
-        .unwrap_or(0);
+        if !keep_alive {
+            break;
+        }
+    }
+}
+
+/// Waits for the next request on a kept-alive connection. False once the client closes
+/// it, or, unless the request is already buffered, it stays idle for `idle_timeout`,
+/// another connection waits for a worker, or the server shuts down.
+fn await_request(reader: &mut BufReader<&TcpStream>, server: &Server) -> bool {
+    // A pipelined request already read from the socket is answered whatever else happens.
+    if !reader.buffer().is_empty() {
+        return true;
+    }
+    let deadline = Instant::now() + server.config.idle_timeout;
+    while server.queued.get() == 0 && !server.shutdown.load(Ordering::SeqCst) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+        if reader.get_ref().set_read_timeout(Some(left.min(IDLE_POLL))).is_err() {
+            return false;
+        }
+        match reader.fill_buf() {
+            Ok(buffered) => return !buffered.is_empty(),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(_) => return false,
+        }
+    }
+    false
+}
 
-    let mut body = vec![0; content_length];
-    buf_reader.read_exact(&mut body).unwrap();
+fn response_request_error(e: RequestError) -> Response {
+    match e {
+        RequestError::Closed => unreachable!("closed connections get no response"),
+        RequestError::BadRequest(e) => response_400(&e),
+        RequestError::PayloadTooLarge(e) => response_error(&Error::TooLarge(e)),
+        RequestError::HeadersTooLarge => Response::new(431).error("The request headers are too large"),
+        RequestError::Timeout => Response::new(408).error("The request was not received in time"),
+        RequestError::NotImplemented(feature) => Response::new(501).error(&format!("{feature} is not supported")),
+        RequestError::VersionNotSupported(version) => Response::new(505).error(&format!("{version} is not supported")),
+    }
+}
+
+/// Serves a file of the web client; browsers revalidate it on every load.
+fn static_file(path: &Path) -> Response {
+    match fs::read(path) {
+        Ok(contents) => Response::new(200).file(path, contents).with_cache_control("no-cache"),
+        // The only error page meant for a browser rather than the API.
+        Err(_) => Response::new(404).html("<h1>404</h1>"),
+    }
+}
 
-    
-    let response = match request_line.as_str() {
-        "GET / HTTP/1.1" => {
-            let contents = fs::read_to_string("src/client/index.html").unwrap();
-            response_200(contents)
+/// `POST /setSigma`: sets the default sigma of the caller's session.
+fn set_sigma(request: &Request, cv: &Arc<Mutex<ComputerVison>>) -> Response {
+    with_session(request, cv, |session| {
+        let Some(sigma) = parse_number(&request.body) else {
+            return response_error(&Error::InvalidParameter("sigma must be a number".into()));
+        };
+        if let Some(session) = cv.lock().unwrap().sessions.get_mut(session) {
+            session.sigma = Some(sigma);
         }
-        "GET /app.js HTTP/1.1" => {
-            let contents = fs::read_to_string("src/client/app.js").unwrap();
-            response_200(contents)
+
+        Response::new(200).text("Ok")
+    })
+}
+
+/// `POST /setThreshold`: sets the default threshold of the caller's session.
+fn set_threshold(request: &Request, cv: &Arc<Mutex<ComputerVison>>) -> Response {
+    with_session(request, cv, |session| {
+        let Some(threshold) = parse_number(&request.body) else {
+            return response_error(&Error::InvalidParameter("threshold must be a number".into()));
+        };
+        if let Some(session) = cv.lock().unwrap().sessions.get_mut(session) {
+            session.threshold = Some(threshold);
         }
-        "GET /style.css HTTP/1.1" => {
-            let contents = fs::read_to_string("src/client/style.css").unwrap();
-            response_200(contents)
+
+        Response::new(200).text("Ok")
+    })
+}
+
+/// Runs `handler` with the caller's session id, creating the session and
+/// setting its cookie when the request did not carry a valid one.
+fn with_session(request: &Request, cv: &Arc<Mutex<ComputerVison>>, handler: impl FnOnce(&str) -> Response) -> Response {
+    let (session, created) = cv.lock().unwrap().sessions.resolve(session::cookie(request));
+
+    let response = handler(&session);
+    if created {
+        response.with_header("Set-Cookie", &session::set_cookie(&session))
+    } else {
+        response
+    }
+}
+
+fn parse_number(body: &[u8]) -> Option<f32> {
+    str::from_utf8(body).ok()?.trim().parse().ok()
+}
+
+/// `POST /detect/{algorithm}`: runs the named detector, or all of them for `all`.
+fn detect(request: &Request, cv: &Arc<Mutex<ComputerVison>>) -> Response {
+    let algorithm = request.param("algorithm").unwrap_or_default();
+    if algorithm == "all" {
+        return run_all(request, cv);
+    }
+
+    match DETECTORS.iter().find(|(name, _)| *name == algorithm) {
+        Some((name, detector)) => {
+            debug!("Start Processing {name}");
+            run_detector(request, cv, *detector)
         }
-        "POST /setSigma HTTP/1.1" => {
-            let t = str::from_utf8(&body).unwrap().parse::<f32>().unwrap();
-            let mut cv = cv.lock().unwrap();
-            cv.sigma = t;
+        None => Response::new(404).error(&format!("Unknown algorithm: {algorithm}")),
+    }
+}
+
+/// The caller's existing session, if any. Detector requests never create one, so
+/// stateless API clients do not push the sessions of browser users out.
+fn existing_session(request: &Request, cv: &Arc<Mutex<ComputerVison>>) -> Option<String> {
+    cv.lock().unwrap().sessions.find(session::cookie(request))
+}
+
+fn run_detector(request: &Request, cv: &Arc<Mutex<ComputerVison>>, detector: Detector) -> Response {
+    let session = existing_session(request, cv);
+    run_single(request, cv, session.as_deref(), detector).unwrap_or_else(|e| response_error(&e))
+}
+
+fn run_single(request: &Request, cv: &Arc<Mutex<ComputerVison>>, session: Option<&str>, detector: Detector) -> Result<Response> {
+    let (image, params) = prepare(request, cv, session)?;
+    // Requests running at the same time share the cores unless `threads` is set.
+    let _busy = parallel::busy();
+
+    let now = Instant::now();
+    let decoded = Decoded::new(&image, params.sigma, params.border)?;
+    let encoded = detector(&decoded, &params)?.encode()?;
+    info!("Elapsed time: {:.2?}", now.elapsed());
+
+    let mut data = serde_json::Map::new();
+    if let Some(base64_image) = encoded.base64 {
+        data.insert("base64".to_string(), Value::String(base64_image));
+    }
+    if let Some(keypoints) = encoded.keypoints {
+        data.insert("keypoints".to_string(), keypoints_json(&keypoints));
+    }
+
+    Ok(response_json(json!({
+        "data": data
+    })))
+}
+
+fn run_all(request: &Request, cv: &Arc<Mutex<ComputerVison>>) -> Response {
+    debug!("Start Processing All");
+    let session = existing_session(request, cv);
+    run_all_detectors(request, cv, session.as_deref()).unwrap_or_else(|e| response_error(&e))
+}
+
+fn run_all_detectors(request: &Request, cv: &Arc<Mutex<ComputerVison>>, session: Option<&str>) -> Result<Response> {
+    let (image, params) = prepare(request, cv, session)?;
+    // Requests running at the same time share the cores unless `threads` is set.
+    let _busy = parallel::busy();
+
+    let now = Instant::now();
+
+    // Decode and blur once; the detectors only read the shared intermediates.
+    let decoded = Decoded::new(&image, params.sigma, params.border)?;
+    let decode_time = now.elapsed();
+    decoded.blurred()?;
+    let blur_time = now.elapsed() - decode_time;
+
+    let results: Vec<(&str, Result<Encoded>, Duration)> = thread::scope(|scope| {
+        let handles: Vec<_> = DETECTORS
+            .iter()
+            .map(|(name, detector)| {
+                let decoded = &decoded;
+                let params = &params;
+                let handle = scope.spawn(move || {
+                    let start = Instant::now();
+                    let encoded = detector(decoded, params).and_then(Detection::encode);
+                    (encoded, start.elapsed())
+                });
+                (*name, handle)
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|(name, handle)| match handle.join() {
+                Ok((encoded, elapsed)) => (name, encoded, elapsed),
+                Err(_) => (name, Err(Error::Io(io::Error::other(format!("{name} detector panicked")))), Duration::ZERO),
+            })
+            .collect()
+    });
+
+    let total_time = now.elapsed();
+    info!("Elapsed time: {:.2?}", total_time);
 
-            response_200("Ok".to_string())
+    let mut data = serde_json::Map::new();
+    let mut keypoints = serde_json::Map::new();
+    let mut timings = serde_json::Map::new();
+    timings.insert("decode".to_string(), json!(milliseconds(decode_time)));
+    timings.insert("blur".to_string(), json!(milliseconds(blur_time)));
+
+    for (name, encoded, elapsed) in results {
+        let encoded = encoded?;
+        if let Some(base64_image) = encoded.base64 {
+            data.insert(name.to_string(), Value::String(base64_image));
+        }
+        if let Some(corners) = encoded.keypoints {
+            keypoints.insert(name.to_string(), keypoints_json(&corners));
         }
-        "POST /setThreshold HTTP/1.1" => {
-            let t = str::from_utf8(&body).unwrap().parse::<f32>().unwrap();
-            let mut cv = cv.lock().unwrap();
-            cv.threshold = t;
+        timings.insert(name.to_string(), json!(milliseconds(elapsed)));
+    }
+    timings.insert("total".to_string(), json!(milliseconds(total_time)));
+
+    let mut body = json!({
+        "data": data,
+        "timings": timings
+    });
+    if !keypoints.is_empty() {
+        body["keypoints"] = Value::Object(keypoints);
+    }
+    Ok(response_json(body))
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Resolves the image and parameters of a detector request. An upload without
+/// an image falls back to the last image uploaded in the caller's session, and
+/// uploads are only cached for callers with a session.
+fn prepare(request: &Request, cv: &Arc<Mutex<ComputerVison>>, session: Option<&str>) -> Result<(Vec<u8>, Params)> {
+    let payload = payload(request)?;
 
-            response_200("Ok".to_string())
+    let mut cv = cv.lock().unwrap();
+    let params = request_params(request, &payload, cv.params(session))?;
+
+    // The session may have been evicted since it was looked up; it then counts as absent.
+    let session = session.filter(|session| cv.sessions.get(session).is_some());
+    let image = if payload.image.is_empty() {
+        let cached = session.and_then(|session| cv.sessions.get(session)?.image());
+        cached.map(<[u8]>::to_vec).ok_or_else(|| Error::InvalidParameter("No image uploaded".into()))?
+    } else {
+        if let Some(session) = session {
+            cv.sessions.cache_image(session, payload.image.clone());
         }
-        "POST /canny HTTP/1.1" => {
-            println!("Start Processing canny");
-            let now = Instant::now();
-            println!("Elapsed time: {:.2?}", now.elapsed());
-            let base64_image = {
-                let mut cv = cv.lock().unwrap();
-                cv.canny()
-            };
-            response_json(json!({
-                "data": {
-                    "base64": base64_image
-                }
-            }))
+        payload.image
+    };
+
+    Ok((image, params))
+}
+
+/// Splits a request body into the image and the other fields. Accepts raw image
+/// bytes, multipart/form-data with a `file` part, or JSON with a base64 `image`.
+fn payload(request: &Request) -> Result<Payload> {
+    let content_type = request.header("content-type").unwrap_or_default();
+
+    if multipart::is_multipart(content_type) {
+        let form = Multipart::parse(content_type, &request.body)?;
+        let mut payload = Payload { image: Vec::new(), fields: HashMap::new() };
+
+        if let Some(part) = form.part("file") {
+            debug!(
+                "Upload: {} ({}, {} bytes)",
+                part.filename.as_deref().unwrap_or("<unnamed>"),
+                part.content_type().unwrap_or("unknown type"),
+                part.data.len()
+            );
+            payload.image = part.data.clone();
         }
-        "POST /sobel HTTP/1.1" => {
-            println!("Start Processing sobel");
-            let now = Instant::now();
-            println!("Elapsed time: {:.2?}", now.elapsed());
-            let base64_image = {
-                let mut cv = cv.lock().unwrap();
-                 cv.sobel() 
-                };
-            response_json(json!({
-                "data": {
-                    "base64": base64_image
-                }
-            }))
+        for part in form.parts.iter().filter(|part| part.name != "file") {
+            if let Some(value) = part.text() {
+                payload.fields.insert(part.name.clone(), value.to_string());
+            }
         }
-        "POST /harris HTTP/1.1" => {
-            println!("Start Processing Harris");
-            let now = Instant::now();
-            println!("Elapsed time: {:.2?}", now.elapsed());
-            let base64_image = {
-                let mut cv = cv.lock().unwrap();
-                cv.harris() 
-            };
-            response_json(json!({
-                "data": {
-                    "base64": base64_image
+        return Ok(payload);
+    }
+
+    if content_type.to_lowercase().starts_with("application/json") {
+        let Value::Object(object) = serde_json::from_slice(&request.body).map_err(|e| Error::Decode(format!("Invalid JSON: {e}")))? else {
+            return Err(Error::Decode("JSON body must be an object".into()));
+        };
+        let mut payload = Payload { image: Vec::new(), fields: HashMap::new() };
+
+        for (key, value) in object {
+            match (key.as_str(), value) {
+                ("image", Value::String(image)) => {
+                    payload.image = STANDARD.decode(image).map_err(|e| Error::Decode(format!("Invalid base64 image: {e}")))?;
                 }
-            }))
-        }
-        "POST /shi HTTP/1.1" => {
-            println!("Start Processing Shi");
-            let now = Instant::now();
-            println!("Elapsed time: {:.2?}", now.elapsed());
-            let base64_image = {
-                let mut cv = cv.lock().unwrap();
-                cv.shi()
-            };
-            response_json(json!({
-                "data": {
-                    "base64": base64_image
+                (_, Value::String(value)) => {
+                    payload.fields.insert(key, value);
                 }
-            }))
-        }
-        "POST /all HTTP/1.1" => {
-            let (canny_tx, canny_rx) = mpsc::channel();
-            let (sobel_tx, sobel_rx) = mpsc::channel();
-            let (harris_tx, harris_rx) = mpsc::channel();
-            let (shi_tx, shi_rx) = mpsc::channel();
-
-            let cv_arc = Arc::clone(&cv);
-
-            println!("Start Processing All");
-            let now = Instant::now();
-
-            let canny_handle = {
-                let cv_clone = Arc::clone(&cv_arc);
-                thread::spawn(move || {
-                   let mut cv = cv_clone.lock().unwrap();
-                   let canny_image = cv.canny(); 
-                   canny_tx.send(canny_image).unwrap();
-                })
-            };
-            let sobel_handle = {
-                let cv_clone = Arc::clone(&cv_arc);
-                thread::spawn(move || {
-                   let mut cv = cv_clone.lock().unwrap();
-                   let sobel_image = cv.sobel(); 
-                   sobel_tx.send(sobel_image).unwrap();
-                })
-            };
-            let harris_handle = {
-                let cv_clone = Arc::clone(&cv_arc);
-                thread::spawn(move || {
-                   let mut cv = cv_clone.lock().unwrap();
-                   let harris_image = cv.harris(); 
-                   harris_tx.send(harris_image).unwrap();
-                })
-            };
-            let shi_handle = {
-                let cv_clone = Arc::clone(&cv_arc);
-                thread::spawn(move || {
-                   let mut cv = cv_clone.lock().unwrap();
-                   let shi_image = cv.shi(); 
-                   shi_tx.send(shi_image).unwrap();
-                })
-            };
-
-
-            canny_handle.join().unwrap();
-            sobel_handle.join().unwrap();
-            harris_handle.join().unwrap();
-            shi_handle.join().unwrap();
-
-            println!("Elapsed time: {:.2?}", now.elapsed());
-
-            response_json(json!({
-                "data": {
-                    "canny": canny_rx.recv().unwrap(),
-                    "sobel": sobel_rx.recv().unwrap(),
-                    "harris": harris_rx.recv().unwrap(),
-                    "shi": shi_rx.recv().unwrap()
+                (_, value) => {
+                    payload.fields.insert(key, value.to_string());
                 }
-            }))
-        }
-        _ => {
-            println!("Request line: {}", request_line);
-            response_404()
+            }
         }
-    };
+        return Ok(payload);
+    }
+
+    Ok(Payload { image: request.body.clone(), fields: HashMap::new() })
+}
+
+/// Applies the detector parameters from the query string and then the body fields on top
+/// of the session defaults. Nothing here changes the shared state.
+fn request_params(request: &Request, payload: &Payload, params: Params) -> Result<Params> {
+    apply_params(|name| payload.fields.get(name).or(request.query.get(name)), params)
+}
+
+/// Names of the detector parameters understood by [`apply_params`].
+const PARAMETERS: &[&str] = &[
+    "sigma", "threshold", "low", "high", "nms", "normalize", "kernel", "kernel_x", "kernel_y", "k", "nms_radius", "window_sigma",
+    "quality_level", "min_distance", "max_corners", "output", "subpixel", "overlay", "edge_color", "marker", "border",
+];
+
+/// Applies the detector parameters of [`PARAMETERS`] that `lookup` finds.
+fn apply_params<'a>(lookup: impl Fn(&str) -> Option<&'a String>, mut params: Params) -> Result<Params> {
+    let invalid = |name: &str, value: &str| Error::InvalidParameter(format!("{name} must be a number, got {value:?}"));
+
+    if let Some(sigma) = lookup("sigma") {
+        params.sigma = sigma.trim().parse().map_err(|_| invalid("sigma", sigma))?;
+    }
+    if let Some(threshold) = lookup("threshold") {
+        params.threshold = threshold.trim().parse().map_err(|_| invalid("threshold", threshold))?;
+        params.harris_threshold = Some(params.threshold);
+    }
+    if let Some(low) = lookup("low") {
+        params.low = Some(low.trim().parse().map_err(|_| invalid("low", low))?);
+    }
+    if let Some(high) = lookup("high") {
+        params.high = Some(high.trim().parse().map_err(|_| invalid("high", high))?);
+    }
+    if let Some(nms) = lookup("nms") {
+        params.suppression = Suppression::parse(nms.trim()).ok_or_else(|| {
+            Error::InvalidParameter(format!("nms must be \"quantized\" or \"interpolated\", got {nms:?}"))
+        })?;
+    }
+    if let Some(normalize) = lookup("normalize") {
+        params.normalization = Normalization::parse(normalize.trim()).ok_or_else(|| {
+            Error::InvalidParameter(format!("normalize must be clamp, minmax, max or log, got {normalize:?}"))
+        })?;
+    }
+    if let Some(k) = lookup("k") {
+        params.k = k.trim().parse().map_err(|_| invalid("k", k))?;
+    }
+    if let Some(nms_radius) = lookup("nms_radius") {
+        params.nms_radius = nms_radius.trim().parse().map_err(|_| invalid("nms_radius", nms_radius))?;
+    }
+    if let Some(window_sigma) = lookup("window_sigma") {
+        params.window_sigma = Some(window_sigma.trim().parse().map_err(|_| invalid("window_sigma", window_sigma))?);
+    }
+    if let Some(quality_level) = lookup("quality_level") {
+        params.quality_level = quality_level.trim().parse().map_err(|_| invalid("quality_level", quality_level))?;
+    }
+    if let Some(min_distance) = lookup("min_distance") {
+        params.min_distance = min_distance.trim().parse().map_err(|_| invalid("min_distance", min_distance))?;
+    }
+    if let Some(max_corners) = lookup("max_corners") {
+        params.max_corners = max_corners.trim().parse().map_err(|_| invalid("max_corners", max_corners))?;
+    }
+    if let Some(output) = lookup("output") {
+        params.output = Output::parse(output.trim()).ok_or_else(|| {
+            Error::InvalidParameter(format!("output must be image, keypoints or both, got {output:?}"))
+        })?;
+    }
+    if let Some(subpixel) = lookup("subpixel") {
+        params.subpixel = subpixel.trim().parse().map_err(|_| {
+            Error::InvalidParameter(format!("subpixel must be true or false, got {subpixel:?}"))
+        })?;
+    }
+    if let Some(overlay) = lookup("overlay") {
+        params.overlay = overlay.trim().parse().map_err(|_| {
+            Error::InvalidParameter(format!("overlay must be true or false, got {overlay:?}"))
+        })?;
+    }
+    if let Some(edge_color) = lookup("edge_color") {
+        params.edge_coloring = EdgeColoring::parse(edge_color.trim()).ok_or_else(|| {
+            Error::InvalidParameter(format!("edge_color must be strength or orientation, got {edge_color:?}"))
+        })?;
+    }
+    if let Some(marker) = lookup("marker") {
+        params.marker = Marker::parse(marker.trim()).ok_or_else(|| {
+            Error::InvalidParameter(format!("marker must be circle or cross, got {marker:?}"))
+        })?;
+    }
+    if let Some(border) = lookup("border") {
+        params.border = BorderMode::parse(border.trim()).ok_or_else(|| {
+            Error::InvalidParameter(format!(
+                "border must be replicate, reflect, reflect101, wrap, constant or skip, got {border:?}"
+            ))
+        })?;
+    }
+    if let Some(kernel) = lookup("kernel") {
+        params.kernel = match kernel.trim() {
+            "custom" => {
+                // Operators are JSON arrays of rows; the y operator defaults to the transposed x operator.
+                let operator = |name: &str| -> Result<Option<Vec<Vec<i32>>>> {
+                    lookup(name)
+                        .map(|value| {
+                            serde_json::from_str(value).map_err(|_| {
+                                Error::InvalidParameter(format!("{name} must be a JSON array of integer rows, got {value:?}"))
+                            })
+                        })
+                        .transpose()
+                };
+                let x = operator("kernel_x")?
+                    .ok_or_else(|| Error::InvalidParameter("kernel=custom needs a kernel_x operator".into()))?;
+                let y = match operator("kernel_y")? {
+                    Some(y) => y,
+                    None => (0..x.len()).map(|row| x.iter().filter_map(|column| column.get(row).copied()).collect()).collect(),
+                };
+                Kernel::Custom(Operators::new(x, y)?)
+            }
+            name => Kernel::parse(name).ok_or_else(|| {
+                Error::InvalidParameter(format!(
+                    "kernel must be sobel3, sobel5, sobel7, scharr, prewitt, roberts or custom, got {kernel:?}"
+                ))
+            })?,
+        };
+    }
+
+    Ok(params)
+}
 
-    println!("sigma: {}, threshold: {}", cv.lock().unwrap().sigma, cv.lock().unwrap().threshold);
+fn keypoints_json(keypoints: &[Keypoint]) -> Value {
+    keypoints
+        .iter()
+        .map(|keypoint| json!({ "x": keypoint.x, "y": keypoint.y, "score": keypoint.score }))
+        .collect()
+}
 
-    stream.write_all(response.as_bytes()).unwrap();
+fn response_400(message: &str) -> Response {
+    Response::new(400).error(message)
 }
 
-fn response_200(contents: String) -> String {
-    let status_line = "HTTP/1.1 200 OK";
-    let length = contents.len();
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    return response;
+/// Maps an error to its status code with a `{"error", "kind"}` JSON body.
+fn response_error(e: &Error) -> Response {
+    let status = match e {
+        Error::Decode(_) | Error::InvalidParameter(_) => 400,
+        Error::TooLarge(_) => 413,
+        Error::UnsupportedFormat(_) => 415,
+        Error::Io(_) => 500,
+    };
+    if status == 500 {
+        error!("Internal error: {e}");
+    }
+
+    Response::new(status).json(&json!({ "error": e.to_string(), "kind": e.kind() }))
 }
 
-fn response_404() -> String {
-    let status_line = "HTTP/1.1 404 Not Found";
-    let contents = "<h1>404</h1>";
-    let length = contents.len();
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    return response;
+fn response_404(request: &Request) -> Response {
+    Response::new(404).error(&format!("No route for {} {}", request.method, request.path))
 }
 
-fn response_json(data: Value) -> String {
-    let status_line = "HTTP/1.1 202 Ok";
-    let contents = data.to_string();
-    let length = contents.len();
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    return response;
+fn response_json(data: Value) -> Response {
+    Response::new(200).json(&data)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: &str, content_type: &str, body: &[u8]) -> Request {
+        let mut raw = format!("POST {path} HTTP/1.1\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n", body.len())
+            .into_bytes();
+        raw.extend_from_slice(body);
+        Request::read(&mut &raw[..], 1 << 20).unwrap()
+    }
+
+    fn multipart(fields: &[(&str, &[u8])]) -> Request {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            let filename = if *name == "file" { "; filename=\"a.png\"" } else { "" };
+            body.extend_from_slice(format!("--XyZ\r\nContent-Disposition: form-data; name=\"{name}\"{filename}\r\n\r\n").as_bytes());
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--XyZ--\r\n");
+        request("/canny?sigma=3", "multipart/form-data; boundary=XyZ", &body)
+    }
+
+    #[test]
+    fn raw_bodies_are_the_image() {
+        let parsed = payload(&request("/canny", "image/png", b"\x89PNG data")).unwrap();
+        assert_eq!(parsed.image, b"\x89PNG data");
+        assert!(parsed.fields.is_empty());
+    }
+
+    #[test]
+    fn multipart_bodies_carry_the_image_and_fields() {
+        let parsed = payload(&multipart(&[("sigma", b"1.5"), ("file", b"\x89PNG\r\n--data"), ("kernel", b"scharr")])).unwrap();
+        assert_eq!(parsed.image, b"\x89PNG\r\n--data");
+        assert_eq!(parsed.fields.len(), 2);
+        assert_eq!(parsed.fields["sigma"], "1.5");
+        assert_eq!(parsed.fields["kernel"], "scharr");
+
+        // Parameters alone leave the image to the session.
+        assert!(payload(&multipart(&[("sigma", b"1.5")])).unwrap().image.is_empty());
+    }
+
+    #[test]
+    fn json_bodies_carry_a_base64_image_and_fields() {
+        let body = json!({ "image": STANDARD.encode(b"pixels"), "sigma": 1.5, "kernel": "scharr", "kernel_x": [[-1, 1], [-1, 1]] });
+        let parsed = payload(&request("/canny", "application/json; charset=utf-8", body.to_string().as_bytes())).unwrap();
+        assert_eq!(parsed.image, b"pixels");
+        assert_eq!(parsed.fields["sigma"], "1.5");
+        assert_eq!(parsed.fields["kernel"], "scharr");
+        assert_eq!(parsed.fields["kernel_x"], "[[-1,1],[-1,1]]");
+
+        for body in [&b"{not json"[..], b"[1, 2]", br#"{"image": "not base64!"}"#] {
+            let result = payload(&request("/canny", "application/json", body));
+            assert!(matches!(result, Err(Error::Decode(_))), "{:?}", String::from_utf8_lossy(body));
+        }
+    }
+
+    /// The sigma a detector request ends up with; the config default is 1.0.
+    fn sigma(request: &Request, session_sigma: Option<f32>) -> f32 {
+        let mut cv = ComputerVison { defaults: Params { sigma: 1.0, ..Params::default() }, sessions: Sessions::default() };
+        let (session, _) = cv.sessions.resolve(None);
+        cv.sessions.get_mut(&session).unwrap().sigma = session_sigma;
+
+        let parsed = payload(request).unwrap();
+        request_params(request, &parsed, cv.params(Some(&session))).unwrap().sigma
+    }
+
+    #[test]
+    fn body_fields_win_over_the_query_the_session_and_the_config() {
+        assert_eq!(sigma(&multipart(&[("sigma", b"4")]), Some(2.0)), 4.0);
+        assert_eq!(sigma(&multipart(&[]), Some(2.0)), 3.0);
+        assert_eq!(sigma(&request("/canny", "image/png", b""), Some(2.0)), 2.0);
+        assert_eq!(sigma(&request("/canny", "image/png", b""), None), 1.0);
+
+        let body = json!({ "sigma": 4 }).to_string();
+        assert_eq!(sigma(&request("/canny?sigma=3", "application/json", body.as_bytes()), Some(2.0)), 4.0);
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let request = request("/canny?sigma=abc", "image/png", b"");
+        let parsed = payload(&request).unwrap();
+        assert!(matches!(request_params(&request, &parsed, Params::default()), Err(Error::InvalidParameter(_))));
+    }
+}
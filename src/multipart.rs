@@ -0,0 +1,268 @@
+/*
+###########################################################################
+# Reference: https://datatracker.ietf.org/doc/html/rfc7578 (form-data)     #
+#            https://datatracker.ietf.org/doc/html/rfc2046#section-5.1.1  #
+###########################################################################
+*/
+
+use rust_server_playground::error::{Error, Result};
+
+#[derive(Clone, Debug)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("content-type")
+    }
+
+    /// The part body as text, for plain form fields like `sigma`.
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.data).ok()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Multipart {
+    pub parts: Vec<Part>,
+}
+
+impl Multipart {
+    /// Parses a multipart/form-data body using the boundary from its Content-Type header.
+    pub fn parse(content_type: &str, body: &[u8]) -> Result<Multipart> {
+        let boundary = boundary(content_type).ok_or_else(|| malformed("Missing multipart boundary"))?;
+        let dash_boundary = format!("--{boundary}");
+
+        // Everything before the first boundary is preamble and gets ignored.
+        let first = if body.starts_with(dash_boundary.as_bytes()) {
+            dash_boundary.len()
+        } else {
+            find_delimiter(body, &dash_boundary).ok_or_else(|| malformed("Missing multipart boundary in body"))?.1
+        };
+        let mut rest = &body[first..];
+
+        let mut parts = Vec::new();
+        loop {
+            if rest.starts_with(b"--") {
+                break;
+            }
+
+            // Skip transport padding up to the line break after the boundary.
+            let padding = rest.iter().take_while(|b| **b == b' ' || **b == b'\t').count();
+            rest = &rest[padding..];
+            rest = rest
+                .strip_prefix(b"\r\n")
+                .or_else(|| rest.strip_prefix(b"\n"))
+                .ok_or_else(|| malformed("Malformed multipart boundary line"))?;
+
+            let (end, next) = find_delimiter(rest, &dash_boundary).ok_or_else(|| malformed("Missing closing multipart boundary"))?;
+            parts.push(parse_part(&rest[..end])?);
+            rest = &rest[next..];
+        }
+
+        Ok(Multipart { parts })
+    }
+
+    pub fn part(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|part| part.name == name)
+    }
+}
+
+/// Extracts the `boundary` parameter of a multipart Content-Type header.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().to_lowercase().starts_with("multipart/") {
+        return None;
+    }
+
+    header_params(params)
+        .into_iter()
+        .find(|(key, _)| key == "boundary")
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+pub fn is_multipart(content_type: &str) -> bool {
+    content_type.trim().to_lowercase().starts_with("multipart/form-data")
+}
+
+fn parse_part(part: &[u8]) -> Result<Part> {
+    let (raw_headers, data) = if let Some(data) = part.strip_prefix(b"\r\n").or_else(|| part.strip_prefix(b"\n")) {
+        (&part[..0], data)
+    } else {
+        // The headers end at the first empty line, whether lines end in CRLF or a bare LF.
+        let crlf = find_bytes(part, b"\r\n\r\n").map(|end| (end, end + 4));
+        let lf = find_bytes(part, b"\n\n").map(|end| (end, end + 2));
+        let (header_end, data_start) = match (crlf, lf) {
+            (Some(crlf), Some(lf)) => crlf.min(lf),
+            (crlf, lf) => crlf.or(lf).ok_or_else(|| malformed("Malformed multipart part headers"))?,
+        };
+        (&part[..header_end], &part[data_start..])
+    };
+
+    let raw_headers = std::str::from_utf8(raw_headers).map_err(|_| malformed("Multipart part headers are not valid UTF-8"))?;
+    let mut headers = Vec::new();
+    for line in raw_headers.lines().filter(|line| !line.trim().is_empty()) {
+        let (key, value) = line.split_once(':').ok_or_else(|| malformed("Malformed multipart part header"))?;
+        headers.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    };
+
+    let disposition = header("content-disposition").ok_or_else(|| malformed("Multipart part without Content-Disposition"))?;
+    let (kind, params) = disposition.split_once(';').unwrap_or((&disposition, ""));
+    if !kind.trim().eq_ignore_ascii_case("form-data") {
+        return Err(malformed(&format!("Unsupported multipart disposition: {}", kind.trim())));
+    }
+
+    let params = header_params(params);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    Ok(Part {
+        name: param("name").ok_or_else(|| malformed("Multipart part without a name"))?,
+        filename: param("filename"),
+        headers,
+        data: data.to_vec(),
+    })
+}
+
+/// Parses `; key=value; key="quoted value"` header parameters. Keys are lowercased.
+fn header_params(params: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let mut chars = params.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| *c == ';' || c.is_whitespace()) {
+            chars.next();
+        }
+
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if key.trim().is_empty() {
+            break;
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+            // Drop anything up to the next separator.
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+            }
+        } else {
+            value = chars.by_ref().take_while(|c| *c != ';').collect();
+        }
+
+        result.push((key.trim().to_lowercase(), value.trim().to_string()));
+    }
+
+    result
+}
+
+fn malformed(message: &str) -> Error {
+    Error::Decode(message.to_string())
+}
+
+/// Finds the next `dash_boundary` at the start of a line. Returns where the data before
+/// it ends, excluding the CRLF or LF, and where the rest after the boundary starts.
+fn find_delimiter(data: &[u8], dash_boundary: &str) -> Option<(usize, usize)> {
+    let delimiter = format!("\n{dash_boundary}");
+    let line_break = find_bytes(data, delimiter.as_bytes())?;
+    let end = if line_break > 0 && data[line_break - 1] == b'\r' { line_break - 1 } else { line_break };
+    Some((end, line_break + delimiter.len()))
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=XyZ";
+
+    #[test]
+    fn preamble_and_epilogue_are_ignored() {
+        let body = b"This is the preamble.\r\n--XyZ\r\nContent-Disposition: form-data; name=\"sigma\"\r\n\r\n1.4\r\n--XyZ--\r\nepilogue";
+        let form = Multipart::parse(CONTENT_TYPE, body).unwrap();
+        assert_eq!(form.parts.len(), 1);
+        assert_eq!(form.part("sigma").unwrap().text(), Some("1.4"));
+    }
+
+    #[test]
+    fn quoted_filenames_are_unescaped() {
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"my \\\"best\\\" shot; v2.png\"\r\n\
+Content-Type: image/png\r\n\r\nPNG\r\n--XyZ--";
+        let form = Multipart::parse(CONTENT_TYPE, body).unwrap();
+        let part = form.part("file").unwrap();
+        assert_eq!(part.filename.as_deref(), Some("my \"best\" shot; v2.png"));
+        assert_eq!(part.content_type(), Some("image/png"));
+        assert_eq!(part.data, b"PNG");
+    }
+
+    #[test]
+    fn a_part_without_headers_has_no_name() {
+        let body = b"--XyZ\r\n\r\ndata\r\n--XyZ--";
+        assert!(matches!(Multipart::parse(CONTENT_TYPE, body), Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn missing_closing_boundary_is_a_decode_error() {
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"sigma\"\r\n\r\n1.4\r\n";
+        assert!(matches!(Multipart::parse(CONTENT_TYPE, body), Err(Error::Decode(_))));
+        assert!(matches!(Multipart::parse(CONTENT_TYPE, b"no boundary at all"), Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn lf_only_line_endings() {
+        let body = b"--XyZ\nContent-Disposition: form-data; name=\"sigma\"\n\n1.4\n--XyZ\n\
+Content-Disposition: form-data; name=\"threshold\"\n\n0.2\n--XyZ--\n";
+        let form = Multipart::parse(CONTENT_TYPE, body).unwrap();
+        assert_eq!(form.part("sigma").unwrap().text(), Some("1.4"));
+        assert_eq!(form.part("threshold").unwrap().text(), Some("0.2"));
+    }
+
+    #[test]
+    fn binary_data_may_contain_line_breaks_and_dashes() {
+        let data = b"\x89PNG\r\n--Xy\r\n--\r\n\x00\xff";
+        let mut body = b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\n\r\n".to_vec();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+
+        let form = Multipart::parse(CONTENT_TYPE, &body).unwrap();
+        assert_eq!(form.part("file").unwrap().data, data);
+    }
+}
@@ -0,0 +1,200 @@
+use image::{GrayImage, Rgb, RgbImage};
+
+use crate::{keypoint::Keypoint, ResponseImage};
+
+/// How edge pixels are coloured when drawn over an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EdgeColoring {
+    /// Blue for the weakest gradient up to red for the strongest.
+    #[default]
+    Strength,
+    /// Hue follows the gradient orientation, so opposite edges share a colour.
+    Orientation,
+}
+
+impl EdgeColoring {
+    pub fn parse(name: &str) -> Option<EdgeColoring> {
+        match name {
+            "strength" => Some(EdgeColoring::Strength),
+            "orientation" => Some(EdgeColoring::Orientation),
+            _ => None,
+        }
+    }
+}
+
+/// Shape drawn at each keypoint; its size grows with the keypoint's score.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Marker {
+    #[default]
+    Circle,
+    Cross,
+}
+
+impl Marker {
+    pub fn parse(name: &str) -> Option<Marker> {
+        match name {
+            "circle" => Some(Marker::Circle),
+            "cross" => Some(Marker::Cross),
+            _ => None,
+        }
+    }
+}
+
+const CORNER_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+const MIN_MARKER_RADIUS: f32 = 3.0;
+const MAX_MARKER_RADIUS: f32 = 10.0;
+
+/// Blends edges over `base`. Each pixel of `edges` is the opacity of the edge colour
+/// (0 leaves the base untouched), `magnitude` and `orientation` are the gradients the
+/// colour is taken from (see [`crate::sobel::sobel_operator`]).
+pub fn draw_edges(
+    base: &mut RgbImage,
+    edges: &GrayImage,
+    magnitude: &ResponseImage,
+    orientation: &ResponseImage,
+    coloring: EdgeColoring,
+) {
+    let max_magnitude = magnitude.pixels().map(|p| p.0[0]).fold(0.0, f32::max);
+
+    for (x, y, pixel) in base.enumerate_pixels_mut() {
+        let alpha = edges.get_pixel(x, y).0[0] as f32 / 255.0;
+        if alpha == 0.0 {
+            continue;
+        }
+
+        let hue = match coloring {
+            EdgeColoring::Strength => {
+                let strength = if max_magnitude > 0.0 { magnitude.get_pixel(x, y).0[0] / max_magnitude } else { 0.0 };
+                240.0 * (1.0 - strength)
+            }
+            EdgeColoring::Orientation => orientation.get_pixel(x, y).0[0] * 2.0,
+        };
+        let color = hue_to_rgb(hue);
+
+        for (channel, edge) in pixel.0.iter_mut().zip(color) {
+            *channel = (*channel as f32 * (1.0 - alpha) + edge as f32 * alpha).round() as u8;
+        }
+    }
+}
+
+/// Draws a marker at every keypoint; the strongest keypoint gets the largest marker.
+pub fn draw_keypoints(base: &mut RgbImage, keypoints: &[Keypoint], marker: Marker) {
+    let max_score = keypoints.iter().map(|k| k.score).fold(0.0, f32::max);
+
+    for keypoint in keypoints {
+        let strength = if max_score > 0.0 { (keypoint.score / max_score).clamp(0.0, 1.0).sqrt() } else { 0.0 };
+        let radius = (MIN_MARKER_RADIUS + (MAX_MARKER_RADIUS - MIN_MARKER_RADIUS) * strength).round() as i64;
+        let (cx, cy) = (keypoint.x.round() as i64, keypoint.y.round() as i64);
+
+        match marker {
+            Marker::Circle => {
+                // Midpoint circle, one point per octant and step.
+                let (mut x, mut y, mut error) = (radius, 0, 1 - radius);
+                while x >= y {
+                    for (dx, dy) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+                        put(base, cx + dx, cy + dy);
+                    }
+                    y += 1;
+                    if error < 0 {
+                        error += 2 * y + 1;
+                    } else {
+                        x -= 1;
+                        error += 2 * (y - x) + 1;
+                    }
+                }
+            }
+            Marker::Cross => {
+                for d in -radius..=radius {
+                    put(base, cx + d, cy);
+                    put(base, cx, cy + d);
+                }
+            }
+        }
+    }
+}
+
+/// Sets a pixel in the corner colour, ignoring positions outside the image.
+fn put(image: &mut RgbImage, x: i64, y: i64) {
+    if x >= 0 && y >= 0 && x < image.width() as i64 && y < image.height() as i64 {
+        image.put_pixel(x as u32, y as u32, CORNER_COLOR);
+    }
+}
+
+/// Fully saturated, full brightness colour of a hue in degrees.
+fn hue_to_rgb(hue: f32) -> [u8; 3] {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    const BASE: Rgb<u8> = Rgb([10, 20, 30]);
+
+    fn corner_pixels(image: &RgbImage) -> Vec<(u32, u32)> {
+        image.enumerate_pixels().filter(|(_, _, p)| **p == CORNER_COLOR).map(|(x, y, _)| (x, y)).collect()
+    }
+
+    #[test]
+    fn transparent_edges_leave_the_base_untouched() {
+        let mut base = RgbImage::from_pixel(8, 8, BASE);
+        let edges = GrayImage::from_fn(8, 8, |x, y| Luma([match (x, y) { (2, 3) => 255, (5, 5) => 128, _ => 0 }]));
+        let magnitude = ResponseImage::from_pixel(8, 8, Luma([100.0]));
+        let orientation = ResponseImage::new(8, 8);
+
+        draw_edges(&mut base, &edges, &magnitude, &orientation, EdgeColoring::Strength);
+
+        for (x, y, pixel) in base.enumerate_pixels() {
+            match (x, y) {
+                // Full strength is red, blended fully or halfway.
+                (2, 3) => assert_eq!(*pixel, Rgb([255, 0, 0])),
+                (5, 5) => assert_eq!(*pixel, Rgb([133, 10, 15])),
+                _ => assert_eq!(*pixel, BASE, "pixel ({x}, {y}) changed"),
+            }
+        }
+    }
+
+    #[test]
+    fn markers_are_clipped_at_the_edges() {
+        // Both markers get the largest radius and reach far outside the image.
+        let keypoints = [Keypoint { x: 0.0, y: 0.0, score: 1.0 }, Keypoint { x: 19.0, y: 14.0, score: 1.0 }];
+        let r = MAX_MARKER_RADIUS as u32;
+
+        let mut base = RgbImage::from_pixel(20, 15, BASE);
+        draw_keypoints(&mut base, &keypoints, Marker::Circle);
+        for (x, y) in [(r, 0), (0, r), (19 - r, 14), (19, 14 - r)] {
+            assert_eq!(*base.get_pixel(x, y), CORNER_COLOR, "({x}, {y})");
+        }
+
+        let mut base = RgbImage::from_pixel(20, 15, BASE);
+        draw_keypoints(&mut base, &keypoints, Marker::Cross);
+        let mut expected: Vec<_> = (0..=r).flat_map(|d| [(d, 0), (0, d), (19 - d, 14), (19, 14 - d)]).collect();
+        expected.sort_by_key(|&(x, y)| (y, x));
+        expected.dedup();
+        assert_eq!(corner_pixels(&base), expected);
+    }
+
+    #[test]
+    fn stronger_keypoints_get_larger_markers() {
+        let mut base = RgbImage::from_pixel(60, 30, BASE);
+        let keypoints = [Keypoint { x: 15.0, y: 15.0, score: 100.0 }, Keypoint { x: 45.0, y: 15.0, score: 1.0 }];
+        draw_keypoints(&mut base, &keypoints, Marker::Cross);
+
+        // Half the width of the horizontal bar of each cross.
+        let radius = |cx: u32| corner_pixels(&base).iter().filter(|&&(x, y)| y == 15 && x.abs_diff(cx) < 15).count() / 2;
+        assert_eq!(radius(15), MAX_MARKER_RADIUS as usize);
+        assert!(radius(45) >= MIN_MARKER_RADIUS as usize && radius(45) < radius(15), "{}", radius(45));
+    }
+}
@@ -0,0 +1,191 @@
+//! Row-band parallelism for the per-pixel stages of the detectors.
+//!
+//! Images are split into bands of consecutive rows, one per thread. Every pixel is
+//! computed by the same code whichever band it lands in, so the results are
+//! bit-identical to running with a single thread.
+
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use image::{ImageBuffer, Luma, Primitive};
+
+/// Images with fewer pixels per band than this are not worth a thread.
+const MIN_BAND_PIXELS: usize = 16 * 1024;
+
+/// Configured number of threads, 0 meaning the cores shared among the [`Busy`] callers.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of live [`Busy`] guards.
+static BUSY: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the number of threads every detector splits its work across; 0 shares the cores
+/// among the callers that are [`busy`].
+pub fn set_threads(threads: usize) {
+    THREADS.store(threads, Ordering::Relaxed);
+}
+
+/// Number of threads the detectors split their work across. Unless set, a lone caller
+/// gets every core and concurrent ones split them evenly, at least one thread each.
+pub fn threads() -> usize {
+    match THREADS.load(Ordering::Relaxed) {
+        0 => {
+            let cores = thread::available_parallelism().map_or(1, |n| n.get());
+            (cores / BUSY.load(Ordering::Relaxed).max(1)).max(1)
+        }
+        threads => threads,
+    }
+}
+
+/// Marks its owner as running detectors until dropped, see [`busy`].
+pub struct Busy(());
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        BUSY.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts the caller among those sharing the cores while the guard lives, e.g. for the
+/// duration of one server request.
+pub fn busy() -> Busy {
+    BUSY.fetch_add(1, Ordering::Relaxed);
+    Busy(())
+}
+
+/// Calls `f(y, row)` for every row of a row-major buffer `width` elements wide,
+/// spreading bands of rows over [`threads`] threads.
+pub fn for_each_row<T, F>(buffer: &mut [T], width: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    if width == 0 || buffer.is_empty() {
+        return;
+    }
+    let height = buffer.len() / width;
+    let band_rows = height.div_ceil(threads()).max(MIN_BAND_PIXELS.div_ceil(width));
+
+    if band_rows >= height {
+        for (y, row) in buffer.chunks_exact_mut(width).enumerate() {
+            f(y, row);
+        }
+        return;
+    }
+
+    thread::scope(|scope| {
+        for (band, rows) in buffer.chunks_mut(band_rows * width).enumerate() {
+            let f = &f;
+            scope.spawn(move || {
+                for (y, row) in rows.chunks_exact_mut(width).enumerate() {
+                    f(band * band_rows + y, row);
+                }
+            });
+        }
+    });
+}
+
+/// Like [`ImageBuffer::from_fn`] for grayscale images, with the rows computed in parallel.
+pub fn luma_from_fn<T, F>(width: u32, height: u32, f: F) -> ImageBuffer<Luma<T>, Vec<T>>
+where
+    T: Primitive + Send,
+    F: Fn(u32, u32) -> T + Sync,
+{
+    let mut pixels = vec![T::DEFAULT_MIN_VALUE; width as usize * height as usize];
+    for_each_row(&mut pixels, width as usize, |y, row| {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = f(x as u32, y as u32);
+        }
+    });
+    ImageBuffer::from_raw(width, height, pixels).expect("buffer has the image size")
+}
+
+/// Runs `f` with the thread count temporarily set, for comparing against the serial path.
+#[cfg(test)]
+pub(crate) fn with_threads<R>(threads: usize, f: impl FnOnce() -> R) -> R {
+    use std::sync::Mutex;
+
+    // Tests run concurrently and the setting is global.
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let previous = THREADS.swap(threads, Ordering::Relaxed);
+    let result = f();
+    THREADS.store(previous, Ordering::Relaxed);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+    use crate::{
+        canny, harris, harris_corners, shi, shi_corners, sobel, BorderMode, CannyParams, HarrisParams, Kernel,
+        Normalization, ShiParams, SobelParams, Suppression,
+    };
+
+    /// Noisy image large enough to be split into several bands.
+    fn noise(width: u32, height: u32) -> GrayImage {
+        let mut state = 0x2545_f491_u32;
+        GrayImage::from_fn(width, height, |x, y| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let blob = if (x / 40 + y / 30) % 2 == 0 { 160 } else { 40 };
+            Luma([(blob + (state >> 24) as i32 % 60) as u8])
+        })
+    }
+
+    #[test]
+    fn rows_are_visited_once_with_their_index() {
+        for threads in [1, 3, 8] {
+            let mut buffer = vec![0usize; 7 * 10_000];
+            with_threads(threads, || for_each_row(&mut buffer, 7, |y, row| row.fill(y + 1)));
+            assert!(buffer.chunks(7).enumerate().all(|(y, row)| row.iter().all(|&v| v == y + 1)));
+        }
+    }
+
+    #[test]
+    fn busy_callers_share_the_cores() {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        with_threads(0, || {
+            let first = busy();
+            assert_eq!(threads(), cores);
+            let second = busy();
+            assert_eq!(threads(), (cores / 2).max(1));
+            drop((first, second));
+            assert_eq!(threads(), cores);
+        });
+        assert_eq!(with_threads(3, threads), 3);
+    }
+
+    #[test]
+    fn threads_match_the_serial_path() {
+        let image = noise(301, 257);
+
+        let run = || {
+            let canny_params = CannyParams { suppression: Suppression::Interpolated, ..CannyParams::default() };
+            let sobel_params = SobelParams {
+                kernel: Kernel::Sobel5,
+                normalization: Normalization::Log,
+                border: BorderMode::Reflect101,
+                ..SobelParams::default()
+            };
+            let harris_params = HarrisParams { subpixel: true, ..HarrisParams::default() };
+            let shi_params = ShiParams { border: BorderMode::Skip, ..ShiParams::default() };
+            (
+                canny(&image, &canny_params).unwrap(),
+                canny(&image, &CannyParams::default()).unwrap(),
+                sobel(&image, &sobel_params).unwrap(),
+                harris(&image, &harris_params).unwrap(),
+                harris_corners(&image, &harris_params).unwrap(),
+                shi(&image, &shi_params).unwrap(),
+                shi_corners(&image, &shi_params).unwrap(),
+            )
+        };
+
+        let serial = with_threads(1, run);
+        for threads in [2, 5, 16] {
+            assert!(with_threads(threads, run) == serial, "{threads} threads");
+        }
+    }
+}
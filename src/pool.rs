@@ -0,0 +1,178 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Returned by [`ThreadPool::execute`] when every worker is busy and the queue is full.
+#[derive(Debug)]
+pub struct PoolFull;
+
+/// Number of queued jobs no worker has picked up yet.
+#[derive(Clone, Debug, Default)]
+pub struct Queued(Arc<AtomicUsize>);
+
+impl Queued {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Fixed set of worker threads fed from a bounded queue. Dropping the pool
+/// lets the workers finish everything already queued before they exit.
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<SyncSender<Job>>,
+    queued: Queued,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize, queue_size: usize) -> ThreadPool {
+        assert!(size > 0, "Thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let queued = Queued::default();
+
+        let workers = (0..size).map(|id| Worker::new(id, Arc::clone(&receiver), queued.clone())).collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+            queued,
+        }
+    }
+
+    /// Live count of the jobs waiting for a worker.
+    pub fn queued(&self) -> Queued {
+        self.queued.clone()
+    }
+
+    /// Queues a job without blocking the caller.
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolFull>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.as_ref().expect("Thread pool is shutting down");
+        // Counted before sending so a worker never sees the job before the count.
+        self.queued.0.fetch_add(1, Ordering::SeqCst);
+        match sender.try_send(Box::new(f)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.queued.0.fetch_sub(1, Ordering::SeqCst);
+                Err(PoolFull)
+            }
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel makes each worker exit once the queue is drained.
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                if thread.join().is_err() {
+                    error!("Worker {} panicked", worker.id);
+                }
+            }
+        }
+    }
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>, queued: Queued) -> Worker {
+        let thread = thread::spawn(move || loop {
+            let job = receiver.lock().unwrap().recv();
+            match job {
+                // A panicking request must not take the worker down with it.
+                Ok(job) => {
+                    queued.0.fetch_sub(1, Ordering::SeqCst);
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("Worker {id} recovered from a panicking job");
+                    }
+                }
+                Err(_) => break,
+            }
+        });
+
+        Worker {
+            id,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Occupies the only worker until the returned sender is dropped.
+    fn block_worker(pool: &ThreadPool) -> mpsc::Sender<()> {
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || while blocked.recv().is_ok() {}).unwrap();
+        // Wait for the worker to take the job off the queue.
+        while pool.queued().get() > 0 {
+            thread::yield_now();
+        }
+        release
+    }
+
+    #[test]
+    fn a_full_queue_is_refused() {
+        let pool = ThreadPool::new(1, 1);
+        let release = block_worker(&pool);
+
+        assert!(pool.execute(|| ()).is_ok());
+        assert_eq!(pool.queued().get(), 1);
+        assert!(pool.execute(|| ()).is_err());
+        assert_eq!(pool.queued().get(), 1);
+
+        drop(release);
+    }
+
+    #[test]
+    fn dropping_the_pool_runs_the_queued_jobs() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(1, 8);
+        let release = block_worker(&pool);
+
+        for _ in 0..5 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        drop(release);
+        drop(pool);
+
+        assert_eq!(done.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn workers_survive_panicking_jobs() {
+        let pool = ThreadPool::new(1, 4);
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(|| panic!("job failed")).unwrap();
+        pool.execute(move || sender.send("after the panic").unwrap()).unwrap();
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok("after the panic"));
+    }
+}
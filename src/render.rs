@@ -0,0 +1,90 @@
+use image::GrayImage;
+
+use crate::{parallel, ResponseImage};
+
+/// How a floating point response is mapped onto 0..=255 for display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Values are rounded and clamped to 0..=255 as they are.
+    #[default]
+    Clamp,
+    /// The smallest value maps to 0 and the largest to 255.
+    MinMax,
+    /// 0 stays 0 and the largest value maps to 255; negative values become 0.
+    MaxOnly,
+    /// Like `MaxOnly` on `ln(1 + v)`, which keeps weak responses visible next to strong ones.
+    Log,
+}
+
+impl Normalization {
+    pub fn parse(name: &str) -> Option<Normalization> {
+        match name {
+            "clamp" => Some(Normalization::Clamp),
+            "minmax" => Some(Normalization::MinMax),
+            "max" => Some(Normalization::MaxOnly),
+            "log" => Some(Normalization::Log),
+            _ => None,
+        }
+    }
+}
+
+/// Renders a response image to 8 bits. Non-finite values render as 0.
+pub fn render(response: &ResponseImage, normalization: Normalization) -> GrayImage {
+    let (min, max) = response
+        .pixels()
+        .map(|p| p.0[0])
+        .filter(|v| v.is_finite())
+        .fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(v), max.max(v)));
+
+    let scale = |v: f32, range: f32| if range > 0.0 { v / range * 255.0 } else { 0.0 };
+    let map = |v: f32| match normalization {
+        Normalization::Clamp => v,
+        Normalization::MinMax => scale(v - min, max - min),
+        Normalization::MaxOnly => scale(v.max(0.0), max),
+        Normalization::Log => scale(v.max(0.0).ln_1p(), max.max(0.0).ln_1p()),
+    };
+
+    parallel::luma_from_fn(response.width(), response.height(), |x, y| {
+        let v = response.get_pixel(x, y).0[0];
+        if v.is_finite() { map(v).round().clamp(0.0, 255.0) as u8 } else { 0 }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    fn response(values: &[f32]) -> ResponseImage {
+        ResponseImage::from_fn(values.len() as u32, 1, |x, _| Luma([values[x as usize]]))
+    }
+
+    fn rendered(values: &[f32], normalization: Normalization) -> Vec<u8> {
+        render(&response(values), normalization).into_raw()
+    }
+
+    const VALUES: [f32; 8] = [-10.0, 0.0, 60.0, 100.0, 300.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY];
+
+    #[test]
+    fn every_mode_maps_the_finite_range() {
+        assert_eq!(rendered(&VALUES, Normalization::Clamp), [0, 0, 60, 100, 255, 0, 0, 0]);
+        // The range is -10..=300, non-finite values do not stretch it.
+        assert_eq!(rendered(&VALUES, Normalization::MinMax), [0, 8, 58, 90, 255, 0, 0, 0]);
+        assert_eq!(rendered(&VALUES, Normalization::MaxOnly), [0, 0, 51, 85, 255, 0, 0, 0]);
+        assert_eq!(rendered(&VALUES, Normalization::Log), [0, 0, 184, 206, 255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn empty_ranges_render_black() {
+        assert_eq!(rendered(&[7.0, 7.0], Normalization::MinMax), [0, 0]);
+        // Negative responses only count for MinMax.
+        assert_eq!(rendered(&[-5.0, -1.0], Normalization::MinMax), [0, 255]);
+        for normalization in [Normalization::MaxOnly, Normalization::Log] {
+            assert_eq!(rendered(&[-5.0, -1.0], normalization), [0, 0], "{normalization:?}");
+        }
+        for normalization in [Normalization::Clamp, Normalization::MinMax, Normalization::MaxOnly, Normalization::Log] {
+            assert_eq!(rendered(&[f32::NAN, f32::INFINITY], normalization), [0, 0], "{normalization:?}");
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use crate::http::{Method, Request, Response};
+
+pub type Handler<S> = Box<dyn Fn(&Request, &S) -> Response + Send + Sync>;
+
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+struct Route<S> {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler<S>,
+}
+
+impl<S> Route<S> {
+    fn matches(&self, path: &[&str]) -> Option<HashMap<String, String>> {
+        if self.segments.len() != path.len() {
+            return None;
+        }
+
+        let mut params = HashMap::new();
+        for (segment, part) in self.segments.iter().zip(path) {
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), part.to_string());
+                }
+            }
+        }
+        Some(params)
+    }
+}
+
+/// Dispatches requests by method and path. Patterns are literal segments or
+/// `{name}` parameters, e.g. `/detect/{algorithm}`.
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+    not_found: Handler<S>,
+}
+
+impl<S> Router<S> {
+    pub fn new<F>(not_found: F) -> Router<S>
+    where
+        F: Fn(&Request, &S) -> Response + Send + Sync + 'static,
+    {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(not_found),
+        }
+    }
+
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Router<S>
+    where
+        F: Fn(&Request, &S) -> Response + Send + Sync + 'static,
+    {
+        let segments = split_path(pattern)
+            .into_iter()
+            .map(|segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => Segment::Param(name.to_string()),
+                None => Segment::Literal(segment.to_string()),
+            })
+            .collect();
+
+        self.routes.push(Route {
+            method,
+            segments,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Router<S>
+    where
+        F: Fn(&Request, &S) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Router<S>
+    where
+        F: Fn(&Request, &S) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// Runs the handler registered for the request. HEAD falls back to the GET
+    /// handler, and a path that exists under other methods answers 405.
+    pub fn handle(&self, request: &mut Request, state: &S) -> Response {
+        let path = split_path(&request.path);
+
+        let mut allowed = Vec::new();
+        let mut fallback = None;
+        for route in &self.routes {
+            let Some(params) = route.matches(&path) else {
+                continue;
+            };
+
+            if route.method == request.method {
+                request.params = params;
+                return (route.handler)(request, state);
+            }
+            if request.method == Method::Head && route.method == Method::Get {
+                fallback = Some((&route.handler, params));
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method.clone());
+            }
+        }
+
+        if let Some((handler, params)) = fallback {
+            request.params = params;
+            return handler(request, state);
+        }
+
+        if allowed.is_empty() {
+            return (self.not_found)(request, state);
+        }
+
+        if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+            allowed.push(Method::Head);
+        }
+        let allow = allowed.iter().map(Method::as_str).collect::<Vec<_>>().join(", ");
+        Response::new(405).error(&format!("{} is not allowed here", request.method)).with_header("Allow", &allow)
+    }
+}
+
+/// Splits a path into segments, ignoring empty ones so `/canny/` matches `/canny`.
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|segment| !segment.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> Request {
+        let raw = format!("{method} {path} HTTP/1.1\r\n\r\n");
+        Request::read(&mut raw.as_bytes(), 0).unwrap()
+    }
+
+    fn router() -> Router<()> {
+        let mut router = Router::new(|_, _| Response::new(404));
+        router
+            .get("/", |_, _| Response::new(200).text("index"))
+            .post("/canny", |_, _| Response::new(200).text("canny"))
+            .post("/detect/{algorithm}", |request, _| Response::new(200).text(request.param("algorithm").unwrap_or_default()));
+        router
+    }
+
+    fn handle(router: &Router<()>, method: &str, path: &str) -> Response {
+        router.handle(&mut request(method, path), &())
+    }
+
+    #[test]
+    fn matches_literals_and_params() {
+        let router = router();
+        assert_eq!(handle(&router, "POST", "/canny").body, b"canny");
+        assert_eq!(handle(&router, "POST", "/canny/").body, b"canny");
+        assert_eq!(handle(&router, "POST", "/detect/harris").body, b"harris");
+        assert_eq!(handle(&router, "POST", "/detect").status, 404);
+        assert_eq!(handle(&router, "POST", "/detect/harris/extra").status, 404);
+        assert_eq!(handle(&router, "GET", "/missing").status, 404);
+    }
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let response = handle(&router(), "HEAD", "/");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"index");
+    }
+
+    #[test]
+    fn other_methods_answer_405_with_allow() {
+        let router = router();
+        let response = handle(&router, "GET", "/canny");
+        assert_eq!(response.status, 405);
+        assert_eq!(response.header("Allow"), Some("POST"));
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["error"], "GET is not allowed here");
+
+        let response = handle(&router, "DELETE", "/");
+        assert_eq!(response.status, 405);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use crate::http::Request;
+
+const COOKIE_NAME: &str = "session";
+
+/// Sessions nobody used for this long are dropped.
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Sessions kept at once; the least recently used one makes way for a new one.
+const MAX_SESSIONS: usize = 1024;
+
+/// Bytes of uploaded images kept across all sessions.
+const MAX_CACHED_BYTES: usize = 256 * 1024 * 1024;
+
+/// Per-client defaults set through `/setSigma` and `/setThreshold`, plus the
+/// client's last upload so it can re-run detectors without sending the image again.
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub sigma: Option<f32>,
+    pub threshold: Option<f32>,
+    /// Set through [`Sessions::cache_image`], which keeps the total within bounds.
+    image: Option<Vec<u8>>,
+    last_seen: Instant,
+}
+
+impl Session {
+    pub fn image(&self) -> Option<&[u8]> {
+        self.image.as_deref()
+    }
+
+    fn new() -> Session {
+        Session {
+            sigma: None,
+            threshold: None,
+            image: None,
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Sessions {
+    sessions: HashMap<String, Session>,
+    max_sessions: usize,
+    max_cached_bytes: usize,
+    /// Bytes of all cached images.
+    cached_bytes: usize,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Sessions::with_limits(MAX_SESSIONS, MAX_CACHED_BYTES)
+    }
+}
+
+impl Sessions {
+    pub fn with_limits(max_sessions: usize, max_cached_bytes: usize) -> Sessions {
+        Sessions {
+            sessions: HashMap::new(),
+            max_sessions: max_sessions.max(1),
+            max_cached_bytes,
+            cached_bytes: 0,
+        }
+    }
+
+    /// Returns the id of the caller's session, creating a new one if the cookie
+    /// is missing or expired. The flag is true when a cookie has to be set.
+    pub fn resolve(&mut self, id: Option<&str>) -> (String, bool) {
+        if let Some(id) = self.find(id) {
+            return (id, false);
+        }
+
+        while self.sessions.len() >= self.max_sessions {
+            let Some(oldest) = self.least_recently_used(|_| true) else { break };
+            self.remove(&oldest);
+        }
+        let id = format!("{:032x}", rand::thread_rng().gen::<u128>());
+        self.sessions.insert(id.clone(), Session::new());
+        (id, true)
+    }
+
+    /// Returns the id of the caller's session if the cookie names a live one, without
+    /// ever creating a session, so stateless clients cannot push others' sessions out.
+    pub fn find(&mut self, id: Option<&str>) -> Option<String> {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| now.duration_since(session.last_seen) >= SESSION_TTL)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            self.remove(&id);
+        }
+
+        let (id, session) = id.and_then(|id| Some((id, self.sessions.get_mut(id)?)))?;
+        session.last_seen = now;
+        Some(id.to_string())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Session> {
+        self.sessions.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Session> {
+        self.sessions.get_mut(id)
+    }
+
+    /// Keeps `image` as the session's last upload. The images of the least recently
+    /// used sessions are dropped to stay within the byte limit, and an image larger
+    /// than the whole limit is not kept at all.
+    pub fn cache_image(&mut self, id: &str, image: Vec<u8>) {
+        let Some(previous) = self.sessions.get_mut(id).map(|session| session.image.take()) else {
+            return;
+        };
+        self.cached_bytes -= previous.map_or(0, |previous| previous.len());
+        if image.len() > self.max_cached_bytes {
+            return;
+        }
+
+        while self.cached_bytes + image.len() > self.max_cached_bytes {
+            let Some(oldest) = self.least_recently_used(|session| session.image.is_some()) else { break };
+            let dropped = self.sessions.get_mut(&oldest).and_then(|session| session.image.take());
+            self.cached_bytes -= dropped.map_or(0, |dropped| dropped.len());
+        }
+        self.cached_bytes += image.len();
+        if let Some(session) = self.sessions.get_mut(id) {
+            session.image = Some(image);
+        }
+    }
+
+    fn least_recently_used(&self, filter: impl Fn(&Session) -> bool) -> Option<String> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| filter(session))
+            .min_by_key(|(_, session)| session.last_seen)
+            .map(|(id, _)| id.clone())
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some(session) = self.sessions.remove(id) {
+            self.cached_bytes -= session.image.map_or(0, |image| image.len());
+        }
+    }
+}
+
+/// Session id sent by the client in its `Cookie` header.
+pub fn cookie(request: &Request) -> Option<&str> {
+    request
+        .header("cookie")?
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, value)| value)
+}
+
+pub fn set_cookie(id: &str) -> String {
+    format!("{COOKIE_NAME}={id}; Path=/; HttpOnly; SameSite=Lax")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_least_recently_used_session_makes_way() {
+        let mut sessions = Sessions::with_limits(2, 100);
+        let (first, _) = sessions.resolve(None);
+        let (second, _) = sessions.resolve(None);
+        // Touching the first session leaves the second as the oldest.
+        assert_eq!(sessions.resolve(Some(&first)), (first.clone(), false));
+
+        let (third, created) = sessions.resolve(None);
+        assert!(created);
+        assert!(sessions.get(&first).is_some());
+        assert!(sessions.get(&second).is_none());
+        assert!(sessions.get(&third).is_some());
+    }
+
+    #[test]
+    fn finding_a_session_never_creates_one() {
+        let mut sessions = Sessions::with_limits(1, 100);
+        let (id, _) = sessions.resolve(None);
+
+        for _ in 0..3 {
+            assert_eq!(sessions.find(None), None);
+            assert_eq!(sessions.find(Some("unknown")), None);
+        }
+        assert_eq!(sessions.find(Some(&id)), Some(id.clone()));
+        assert_eq!(sessions.sessions.len(), 1);
+    }
+
+    #[test]
+    fn cached_images_stay_within_the_byte_limit() {
+        let mut sessions = Sessions::with_limits(10, 100);
+        let (first, _) = sessions.resolve(None);
+        let (second, _) = sessions.resolve(None);
+
+        sessions.cache_image(&first, vec![1; 60]);
+        sessions.cache_image(&second, vec![2; 60]);
+        assert_eq!(sessions.get(&first).unwrap().image(), None);
+        assert_eq!(sessions.get(&second).unwrap().image(), Some(&[2; 60][..]));
+
+        // Replacing an image frees the old one first.
+        sessions.cache_image(&second, vec![3; 90]);
+        assert_eq!(sessions.cached_bytes, 90);
+        // An image above the whole limit is not kept.
+        sessions.cache_image(&first, vec![4; 101]);
+        assert_eq!(sessions.get(&first).unwrap().image(), None);
+        assert_eq!(sessions.cached_bytes, 90);
+    }
+}
@@ -1,77 +1,169 @@
-use std::io::Cursor;
-
-use image::{imageops::grayscale, io::Reader as ImageReader, DynamicImage, GenericImage, ImageBuffer, Luma, Rgba};
-
-pub fn shi(body: &Vec<u8>, threshold: &f32) -> Vec<u8> {
+use image::{GrayImage, Luma};
+
+use crate::{
+    border::BorderMode,
+    error::{Error, Result},
+    keypoint::{local_maxima, refine_subpixel, Keypoint},
+    parallel,
+    tensor::StructureTensor,
+    ResponseImage,
+};
+
+/// Corner selection in the style of OpenCV's `goodFeaturesToTrack`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShiParams {
+    /// Minimum score of a corner, as a fraction of the strongest score in the image.
+    pub quality_level: f32,
+    /// Minimum Euclidean distance in pixels between two returned corners.
+    pub min_distance: f32,
+    /// Maximum number of corners returned, strongest first; 0 means no limit.
+    pub max_corners: usize,
+    /// Standard deviation of the Gaussian window the structure tensor is summed over.
+    pub window_sigma: f32,
+    /// Refine corner positions to sub-pixel accuracy.
+    pub subpixel: bool,
+    /// How the gradients and the window sample outside the image.
+    pub border: BorderMode,
+}
 
-    let img = ImageReader::new(Cursor::new(body)).with_guessed_format().unwrap().decode().unwrap();
+impl Default for ShiParams {
+    fn default() -> Self {
+        ShiParams {
+            quality_level: 0.01,
+            min_distance: 10.0,
+            max_corners: 0,
+            window_sigma: 1.0,
+            subpixel: false,
+            border: BorderMode::Replicate,
+        }
+    }
+}
 
-    let gray_scale_img = grayscale(&img);
-    let corner_img = shi_corner_detection(&gray_scale_img, *threshold as f64);
+/// Shi-Tomasi score of every pixel: the smaller eigenvalue of the structure tensor.
+pub fn shi_response(image: &GrayImage, params: &ShiParams) -> Result<ResponseImage> {
+    let tensor = StructureTensor::new(image, params.window_sigma, params.border)?;
 
-    let mut image_bytes = Vec::new();
-    corner_img.write_to(&mut Cursor::new(&mut image_bytes), image::ImageFormat::Png).unwrap(); 
+    Ok(parallel::luma_from_fn(image.width(), image.height(), |x, y| {
+        let (xx, xy, yy) = tensor.get(x, y);
+        let (xx, xy, yy) = (xx as f64, xy as f64, yy as f64);
 
-    return image_bytes;
+        let half_trace = (xx + yy) / 2.0;
+        let root = (((xx - yy) / 2.0).powi(2) + xy * xy).sqrt();
+        (half_trace - root) as f32
+    }))
 }
 
-fn ix(image: &ImageBuffer<Luma<u8>, Vec<u8>>, x: u32, y: u32) -> f64 {
-    let x1 = if x > 0 { x - 1 } else { x };
-    let x2 = if x < image.width() - 1 { x + 1 } else { x };
-    return (image.get_pixel(x2, y).0[0] as f64 - image.get_pixel(x1, y).0[0] as f64) / 2.0;
-}
-fn iy(image: &ImageBuffer<Luma<u8>, Vec<u8>>, x: u32, y: u32) -> f64 {
-    let y1 = if y > 0 { y - 1 } else { y };
-    let y2 = if y < image.height() - 1 { y + 1 } else { y };
-    return (image.get_pixel(x, y2).0[0] as f64 - image.get_pixel(x, y1).0[0] as f64) / 2.0;
-}
+/// Strongest corners that are local maxima above the quality level and at least
+/// `min_distance` apart, at most `max_corners` of them.
+pub fn shi_corners(image: &GrayImage, params: &ShiParams) -> Result<Vec<Keypoint>> {
+    let quality_level = params.quality_level;
+    if !quality_level.is_finite() || quality_level <= 0.0 || quality_level > 1.0 {
+        return Err(Error::InvalidParameter(format!("quality_level must be in (0, 1], got {quality_level}")));
+    }
+    let min_distance = params.min_distance;
+    if !min_distance.is_finite() || min_distance < 0.0 {
+        return Err(Error::InvalidParameter(format!("min_distance must be a non-negative number, got {min_distance}")));
+    }
 
-fn det(m: &Vec<Vec<f64>>) -> f64 {
-    return m[0][0] * m[1][1] - m[1][0] * m[0][1];
-}
+    let response = shi_response(image, params)?;
+    let max_score = response.pixels().map(|p| p.0[0]).fold(0.0, f32::max);
+    if max_score <= 0.0 {
+        return Ok(Vec::new());
+    }
+    let candidates = local_maxima(&response, 1, quality_level * max_score);
+
+    // Accepted corners bucketed on a grid of `min_distance` cells, so each candidate
+    // only has to be checked against the corners in the surrounding cells.
+    let cell = min_distance.max(1.0);
+    let columns = (image.width() as f32 / cell).ceil() as usize + 1;
+    let rows = (image.height() as f32 / cell).ceil() as usize + 1;
+    let mut grid: Vec<Vec<Keypoint>> = vec![Vec::new(); columns * rows];
+
+    let mut corners = Vec::new();
+    for candidate in candidates {
+        if params.max_corners > 0 && corners.len() == params.max_corners {
+            break;
+        }
+
+        let (column, row) = ((candidate.x / cell) as usize, (candidate.y / cell) as usize);
+        let too_close = (row.saturating_sub(1)..=(row + 1).min(rows - 1)).any(|row| {
+            (column.saturating_sub(1)..=(column + 1).min(columns - 1)).any(|column| {
+                grid[row * columns + column].iter().any(|corner| {
+                    let (dx, dy) = (corner.x - candidate.x, corner.y - candidate.y);
+                    dx * dx + dy * dy < min_distance * min_distance
+                })
+            })
+        });
+        if !too_close {
+            grid[row * columns + column].push(candidate);
+            corners.push(candidate);
+        }
+    }
 
-fn trace(m: &Vec<Vec<f64>>) -> f64 {
-    return m[0][0] + m[1][1];
+    if params.subpixel {
+        return Ok(corners.into_iter().map(|corner| refine_subpixel(&response, corner)).collect());
+    }
+    Ok(corners)
 }
 
-fn shi_corner_detection(image: &ImageBuffer<Luma<u8>, Vec<u8>>, threshold: f64) -> DynamicImage {
-    let width = image.width();
-    let height = image.height();
+/// Marks the selected corners with 3x3 white dots on black.
+pub fn shi(image: &GrayImage, params: &ShiParams) -> Result<GrayImage> {
+    let corners = shi_corners(image, params)?;
 
-    let mut shi_image = DynamicImage::new_luma8(width, height);
+    let mut marked = GrayImage::new(image.width(), image.height());
+    for corner in corners {
+        let (x, y) = (corner.x as u32, corner.y as u32);
+        for ny in y.saturating_sub(1)..=(y + 1).min(image.height() - 1) {
+            for nx in x.saturating_sub(1)..=(x + 1).min(image.width() - 1) {
+                marked.put_pixel(nx, ny, Luma([255]));
+            }
+        }
+    }
 
-    let window_size = 3;
-    let offset = window_size / 2;
-    let k: f64 = 0.04;
+    Ok(marked)
+}
 
-    for y in offset..height-offset {
-        for x in offset..width-offset {
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            let mut sum_ix2 = 0.0;
-            let mut sum_iy2 = 0.0;
-            let mut sum_ixiy = 0.0;
+    /// Dark background with bright squares whose top left corners are given.
+    fn squares(corners: &[(u32, u32)]) -> GrayImage {
+        GrayImage::from_fn(64, 64, |x, y| {
+            let inside = corners.iter().any(|&(cx, cy)| (cx..cx + 12).contains(&x) && (cy..cy + 12).contains(&y));
+            Luma([if inside { 200 } else { 20 }])
+        })
+    }
 
-            for j in 0..window_size {
-                for i in 0..window_size {
-                    let ix = ix(image, x + i as u32 - offset, y + j as u32 - offset);
-                    let iy = iy(image, x + i as u32 - offset, y + j as u32 - offset);
-                    sum_ix2 += ix * ix;
-                    sum_iy2 += iy * iy;
-                    sum_ixiy += ix * iy;
-                }
-            }
+    #[test]
+    fn finds_the_corners_of_a_square() {
+        let corners = shi_corners(&squares(&[(20, 20)]), &ShiParams::default()).unwrap();
+        assert_eq!(corners.len(), 4, "{corners:?}");
+        assert!(corners.windows(2).all(|pair| pair[0].score >= pair[1].score));
+    }
 
-            let a = vec![vec![sum_ix2, sum_ixiy], vec![sum_ixiy, sum_iy2]];
-            let det_a = det(&a);
-            let trace_a = trace(&a);
-            let mc = det_a - k * trace_a * trace_a;
+    #[test]
+    fn min_distance_and_max_corners() {
+        let image = squares(&[(8, 8), (40, 40)]);
 
-            if mc > threshold {
-                shi_image.put_pixel(x, y, Rgba([255,255,255,255]));
-            }
+        let all = shi_corners(&image, &ShiParams { min_distance: 1.0, ..ShiParams::default() }).unwrap();
+        assert_eq!(all.len(), 8, "{all:?}");
 
+        // Corners of a 12 pixel square are 11 pixels apart.
+        let spread = shi_corners(&image, &ShiParams { min_distance: 20.0, ..ShiParams::default() }).unwrap();
+        assert_eq!(spread.len(), 2, "{spread:?}");
+        for (a, b) in spread.iter().zip(spread.iter().skip(1)) {
+            assert!((a.x - b.x).hypot(a.y - b.y) >= 20.0);
         }
+
+        let capped = shi_corners(&image, &ShiParams { min_distance: 1.0, max_corners: 3, ..ShiParams::default() }).unwrap();
+        assert_eq!(capped, all[..3]);
     }
 
-    return shi_image;
-}
\ No newline at end of file
+    #[test]
+    fn edges_score_near_zero() {
+        let image = GrayImage::from_fn(40, 40, |x, _| Luma([if x < 20 { 20 } else { 200 }]));
+        let response = shi_response(&image, &ShiParams::default()).unwrap();
+        assert!(response.get_pixel(20, 20).0[0].abs() < 1e-3);
+    }
+}
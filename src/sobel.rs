@@ -1,93 +1,104 @@
-use std::{f32::consts::PI, io::Cursor};
-
-use image::{imageops::grayscale, io::Reader as ImageReader, DynamicImage, GenericImage, GenericImageView, Rgba};
-
-pub fn sobel(body: &Vec<u8>, sigma: &f32) -> Vec<u8> {
-
-    let image = ImageReader::new(Cursor::new(body)).with_guessed_format().unwrap().decode().unwrap();
-
-    let gray_scale_img = grayscale(&image);
-    //let gausian_image = apply_gausian_filter(gray_scale_img.into(), gausian_strength).unwrap(); // My implementation
-    let gausian_image = image::imageops::blur(&gray_scale_img, *sigma);
-
-
-    let operators = vec![
-        vec![
-        vec![-1, 0, 1],
-        vec![-2, 0, 2],
-        vec![-1, 0, 1],
-    ],
-    vec![
-        vec![-1, -2, -1],
-        vec![0, 0, 0],
-        vec![1, 2, 1],
-    ]
-    ];
-
-    let (gradient_mag, _) = sobel_operator(&gausian_image.into(), operators);
-
-    let mut image_bytes = Vec::new();
-    gradient_mag.write_to(&mut Cursor::new(&mut image_bytes), image::ImageFormat::Png).unwrap(); 
+use std::f32::consts::PI;
+
+use image::GrayImage;
+
+use crate::{
+    error::Result,
+    border::BorderMode,
+    convolution::convolve,
+    gausian_blur::gaussian_blur,
+    kernel::{Kernel, Operators},
+    parallel,
+    render::{render, Normalization},
+    ResponseImage,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SobelParams {
+    /// Standard deviation of the Gaussian blur applied before the gradients.
+    pub sigma: f32,
+    pub kernel: Kernel,
+    /// How the gradient magnitude is mapped to 0..=255 by [`sobel`] and [`sobel_blurred`].
+    pub normalization: Normalization,
+    /// How the blur and the operators sample outside the image.
+    pub border: BorderMode,
+}
 
-    return image_bytes;
+impl Default for SobelParams {
+    fn default() -> Self {
+        SobelParams {
+            sigma: 1.0,
+            kernel: Kernel::Sobel3,
+            normalization: Normalization::Clamp,
+            border: BorderMode::Replicate,
+        }
+    }
 }
 
-pub fn sobel_operator(image: &DynamicImage, operators: Vec<Vec<Vec<i32>>>) -> (DynamicImage, DynamicImage) {
-    let width = image.width() as i32;
-    let height = image.height() as i32;
+/// Sobel gradient magnitude of a grayscale image.
+pub fn sobel(image: &GrayImage, params: &SobelParams) -> Result<GrayImage> {
+    let blurred = gaussian_blur(image, params.sigma, params.border)?;
+    sobel_blurred(&blurred, params)
+}
 
-    // Sobel operator coefficients
-    let sobel_x = operators.get(0).unwrap();
-    let sobel_y = operators.get(1).unwrap();
+/// Sobel gradient magnitude of an image that was already blurred; `params.sigma` is ignored.
+pub fn sobel_blurred(blurred: &GrayImage, params: &SobelParams) -> Result<GrayImage> {
+    Ok(render(&sobel_response(blurred, &params.kernel, params.border)?, params.normalization))
+}
 
-    let mut gradient_magnitude = DynamicImage::new_luma8(image.width(), image.height());
-    let mut gradient_direction = DynamicImage::new_luma8(image.width(), image.height());
+/// Unscaled gradient magnitude of an image that was already blurred.
+pub fn sobel_response(blurred: &GrayImage, kernel: &Kernel, border: BorderMode) -> Result<ResponseImage> {
+    let (gradient_mag, _) = sobel_operator(blurred, &kernel.operators(), border);
+    Ok(gradient_mag)
+}
 
-    let mut max_gradient = 0;
+/// Gradient magnitude and orientation (degrees in `0..180`, see [`orientation`]) of every
+/// pixel, sampling outside the image according to `border`. Magnitudes are scaled to the
+/// gain of the 3x3 Sobel operators, so thresholds mean the same whichever kernel is used.
+pub fn sobel_operator(image: &GrayImage, operators: &Operators, border: BorderMode) -> (ResponseImage, ResponseImage) {
+    let (width, height) = (image.width(), image.height());
+    let (filter_x, filter_y) = operators.filters();
+    let scale = 4.0 / operators.gain() as f32;
+
+    let gx = convolve(image.as_raw(), width as usize, height as usize, &filter_x, border);
+    let gy = convolve(image.as_raw(), width as usize, height as usize, &filter_y, border);
+    let gradient = |x: u32, y: u32| {
+        let i = (y * width + x) as usize;
+        (gx[i] * scale, gy[i] * scale)
+    };
 
     // Compute gradient magnitudes
-    for x in 1..width-1 {
-        for y in 1..height-1 {
-            let mut gx = 0.0;
-            let mut gy = 0.0;
+    let gradient_magnitude = parallel::luma_from_fn(width, height, |x, y| {
+        let (gx, gy) = gradient(x, y);
+        f32::sqrt(gx * gx + gy * gy)
+    });
 
-            for i in -1..=1 {
-                for j in -1..=1 {
-                    let pixel = image.get_pixel((x + i) as u32, (y + j) as u32).0[0] as i32;
-                    gx += (pixel * sobel_x[(i + 1) as usize][(j + 1) as usize]) as f32;
-                    gy += (pixel * sobel_y[(i + 1) as usize][(j + 1) as usize]) as f32;
-                }
-            }
+    // Compute gradient direction
+    let gradient_direction = parallel::luma_from_fn(width, height, |x, y| {
+        let (gx, gy) = gradient(x, y);
+        (orientation(gx, gy) + operators.rotation()) % 180.0
+    });
 
-            let g = f32::sqrt(gx * gx + gy * gy) as i32;
-            max_gradient = max_gradient.max(g);
-            gradient_magnitude.put_pixel(x as u32, y as u32, Rgba([g as u8, g as u8, g as u8, 255]));
+    (gradient_magnitude, gradient_direction)
+}
 
-            // Compute gradient direction
-            let angle = gy.atan2(gx);
-            let angle_deg = (angle * (180.0 / PI)).round() as i32;
-            
-            let normalized_angle = match angle_deg {
-                0..=22 => 0,   // Horizontal
-                23..=68 => 45,   // 45-degree diagonal
-                69..=112 => 90, // vetical-degree diagonal
-                113..=158 => 135, // 135-degree diagonal
-                _ => 0,         // Vertical
-            };
+/// Gradient orientation in degrees, folded into `0..180`. Opposite gradients
+/// (dark-to-bright vs bright-to-dark) describe the same edge. The y axis points
+/// down, so 45 degrees runs from the top left to the bottom right.
+pub(crate) fn orientation(gx: f32, gy: f32) -> f32 {
+    let angle = gy.atan2(gx) * (180.0 / PI);
+    let folded = angle.rem_euclid(180.0);
+    // `rem_euclid` can round up to exactly 180 for tiny negative angles.
+    if folded >= 180.0 { 0.0 } else { folded }
+}
 
-            gradient_direction.put_pixel(x as u32, y as u32, Rgba([normalized_angle as u8, normalized_angle as u8, normalized_angle as u8, 255]));
-            gradient_magnitude.put_pixel(x as u32, y as u32, Rgba([g as u8, g as u8, g as u8, 255]));
-        }
+/// Snaps an orientation from [`orientation`] to 0, 45, 90 or 135 degrees.
+pub(crate) fn quantize_orientation(angle: f32) -> u8 {
+    match angle {
+        a if a < 22.5 => 0,   // Horizontal gradient, vertical edge
+        a if a < 67.5 => 45,  // 45-degree diagonal
+        a if a < 112.5 => 90, // Vertical gradient, horizontal edge
+        a if a < 157.5 => 135, // 135-degree diagonal
+        _ => 0,
     }
-
-    // // Normalize gradient magnitudes
-    // for x in 1..width-1 {
-    //     for y in 1..height-1 {
-    //         let pixel = gradient_magnitude.get_pixel(x as u32, y as u32).0[0] as f32;
-    //         let normalized_pixel = (pixel / max_gradient as f32 * 255.0).min(255.0) as u8;
-    //         gradient_magnitude.put_pixel(x as u32, y as u32, Rgba([normalized_pixel, normalized_pixel, normalized_pixel, 255]));
-    //     }
-    // }
-
-    (gradient_magnitude, gradient_direction)
-}
\ No newline at end of file
+}
@@ -0,0 +1,49 @@
+use image::GrayImage;
+
+use crate::{
+    border::BorderMode,
+    convolution::{convolve, Filter},
+    error::Result,
+    gausian_blur::gaussian_blur_f32,
+    parallel, ResponseImage,
+};
+
+/// Gaussian-weighted sums of the gradient products `Ix²`, `IxIy` and `Iy²` around every pixel.
+pub struct StructureTensor {
+    pub xx: ResponseImage,
+    pub xy: ResponseImage,
+    pub yy: ResponseImage,
+}
+
+impl StructureTensor {
+    /// `window_sigma` is the standard deviation of the Gaussian window; `border` applies to
+    /// both the gradients and the window.
+    pub fn new(image: &GrayImage, window_sigma: f32, border: BorderMode) -> Result<StructureTensor> {
+        let (width, height) = (image.width(), image.height());
+
+        // Central differences; the 3x3 footprint makes a pixel skip both gradients or neither.
+        let difference = [vec![0.0; 3], vec![-0.5, 0.0, 0.5], vec![0.0; 3]];
+        let transposed = [vec![0.0, -0.5, 0.0], vec![0.0; 3], vec![0.0, 0.5, 0.0]];
+        let ix = convolve(image.as_raw(), width as usize, height as usize, &Filter::new(&difference), border);
+        let iy = convolve(image.as_raw(), width as usize, height as usize, &Filter::new(&transposed), border);
+
+        let product = |a: &[f32], b: &[f32]| -> ResponseImage {
+            parallel::luma_from_fn(width, height, |x, y| {
+                let i = (y * width + x) as usize;
+                a[i] * b[i]
+            })
+        };
+        let (xx, xy, yy) = (product(&ix, &ix), product(&ix, &iy), product(&iy, &iy));
+
+        Ok(StructureTensor {
+            xx: gaussian_blur_f32(&xx, window_sigma, border)?,
+            xy: gaussian_blur_f32(&xy, window_sigma, border)?,
+            yy: gaussian_blur_f32(&yy, window_sigma, border)?,
+        })
+    }
+
+    /// The tensor entries `(xx, xy, yy)` at a pixel.
+    pub fn get(&self, x: u32, y: u32) -> (f32, f32, f32) {
+        (self.xx.get_pixel(x, y).0[0], self.xy.get_pixel(x, y).0[0], self.yy.get_pixel(x, y).0[0])
+    }
+}